@@ -12,7 +12,7 @@ pub fn main() {
         let file = match taglib::File::new(arg) {
             Ok(f) => f,
             Err(e) => {
-                println!("Invalid file {} (error: {:?})", arg, e);
+                println!("Invalid file {} (error: {})", arg, e);
                 continue;
             }
         };
@@ -43,39 +43,39 @@ pub fn main() {
                     .map_or_else(|| EMPTY.to_string(), |t| t.to_string()));
 
                 println!("-- PROPERTY --");
-                let result_keys = file.keys();
-                if result_keys.is_ok() {
-                    let keys = result_keys.unwrap();
-                    println!("{} keys.", keys.len());
-                    for key in keys {
-                        println!("{}: {:?}",
-                                 key,
-                                 file.get_property(&key).unwrap_or_default());
+                match file.properties() {
+                    Ok(props) => {
+                        println!("{} keys.", props.len());
+                        for (key, values) in props {
+                            println!("{}: {:?}", key, values);
+                        }
+                    }
+                    Err(e) => {
+                        println!("No available properties for {} (error: {})", arg, e);
                     }
-                } else {
-                    println!("No available properties for {} (error: {:?})",
-                             arg,
-                             result_keys.err().unwrap());
                 }
             }
             Err(e) => {
-                println!("No available tags for {} (error: {:?})", arg, e);
+                println!("No available tags for {} (error: {})", arg, e);
             }
         }
 
         match file.audioproperties() {
             Ok(p) => {
-                let secs = p.length() % 60;
-                let mins = (p.length() - secs) / 60;
+                let length = p.duration();
+                let secs = length.as_secs() % 60;
+                let mins = length.as_secs() / 60;
 
                 println!("-- AUDIO --");
+                println!("file type   - {}", file.file_type()
+                    .map_or_else(|| EMPTY.to_string(), |t| t.to_string()));
                 println!("bitrate     - {}", p.bitrate());
                 println!("sample rate - {}", p.samplerate());
                 println!("channels    - {}", p.channels());
-                println!("length      - {}m:{}s", mins, secs);
+                println!("length      - {}m:{}.{:03}s", mins, secs, length.subsec_millis());
             }
             Err(e) => {
-                println!("No available audio properties for {} (error: {:?})", arg, e);
+                println!("No available audio properties for {} (error: {})", arg, e);
             }
         }
     }
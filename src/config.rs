@@ -0,0 +1,122 @@
+// Copyright 2015  Emmanuele Bassi. All rights reserved.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Process-wide TagLib settings.
+//!
+//! The settings apply to all files in all threads.  TagLib reads them
+//! without synchronization, so they can only be changed until the first
+//! file is opened; afterwards, the setters return `SettingsLockedError`.
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use sys as ll;
+
+use super::SettingsLockedError;
+
+/// The text encoding of newly created ID3v2 text frames.
+///
+/// Existing frames keep the encoding they were read with.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Id3v2Encoding {
+    /// ISO-8859-1
+    Latin1 = 0,
+    /// UTF-16 with a byte order mark
+    Utf16 = 1,
+    /// UTF-16 big endian without a byte order mark; ID3v2.4 only
+    Utf16BE = 2,
+    /// UTF-8; ID3v2.4 only
+    Utf8 = 3,
+}
+
+impl Id3v2Encoding {
+    fn from_id(id: u8) -> Option<Id3v2Encoding> {
+        match id {
+            0 => Some(Id3v2Encoding::Latin1),
+            1 => Some(Id3v2Encoding::Utf16),
+            2 => Some(Id3v2Encoding::Utf16BE),
+            3 => Some(Id3v2Encoding::Utf8),
+            _ => None,
+        }
+    }
+}
+
+// no encoding was chosen, TagLib picks one per frame
+const ID3V2_ENCODING_DEFAULT: u8 = u8::MAX;
+
+static ID3V2_ENCODING: AtomicU8 = AtomicU8::new(ID3V2_ENCODING_DEFAULT);
+static STRINGS_UNICODE: AtomicBool = AtomicBool::new(true);
+// whether the settings were passed to TagLib, after which they are fixed
+static APPLIED: AtomicBool = AtomicBool::new(false);
+
+// Serializes changes to the settings with apply()
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// Sets the text encoding of newly created ID3v2 text frames.
+///
+/// Until this is called, TagLib uses Latin-1 where possible and UTF-16 or
+/// UTF-8 otherwise.
+pub fn set_id3v2_default_text_encoding(encoding: Id3v2Encoding)
+    -> Result<(), SettingsLockedError>
+{
+    let _lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if APPLIED.load(Ordering::SeqCst) {
+        return Err(SettingsLockedError);
+    }
+    ID3V2_ENCODING.store(encoding as u8, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Returns the text encoding of newly created ID3v2 text frames, if one was
+/// set with `set_id3v2_default_text_encoding()`.
+pub fn id3v2_default_text_encoding() -> Option<Id3v2Encoding> {
+    Id3v2Encoding::from_id(ID3V2_ENCODING.load(Ordering::SeqCst))
+}
+
+/// Sets whether strings are exchanged with TagLib as UTF-8, the default, or
+/// as Latin-1.
+///
+/// In Latin-1 mode, values with characters outside of Latin-1 are rejected
+/// with `FileError::InvalidValue`.
+pub fn set_strings_unicode(unicode: bool) -> Result<(), SettingsLockedError> {
+    let _lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if APPLIED.load(Ordering::SeqCst) {
+        return Err(SettingsLockedError);
+    }
+    STRINGS_UNICODE.store(unicode, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Returns whether strings are exchanged with TagLib as UTF-8.
+pub fn strings_unicode() -> bool {
+    STRINGS_UNICODE.load(Ordering::SeqCst)
+}
+
+// Applies the settings to TagLib and locks them, see init()
+pub(crate) fn apply() {
+    let _lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    APPLIED.store(true, Ordering::SeqCst);
+    unsafe {
+        ll::taglib_set_strings_unicode(strings_unicode() as ll::TagLib_Bool);
+        if let Some(encoding) = id3v2_default_text_encoding() {
+            ll::taglib_id3v2_set_default_text_encoding(encoding as ll::TagLib_ID3v2_Encoding);
+        }
+    }
+}
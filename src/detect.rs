@@ -0,0 +1,347 @@
+// Copyright 2015  Emmanuele Bassi. All rights reserved.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use super::{FileError, FileType};
+
+// enough to hold the signatures of all formats, the furthest being the one of
+// MOD files at offset 1080
+const HEADER_LEN: u64 = 4096;
+
+const ASF_GUID: &[u8] = b"\x30\x26\xb2\x75\x8e\x66\xcf\x11\xa6\xd9\x00\xaa\x00\x62\xce\x6c";
+
+const MOD_SIGNATURES: &[&[u8]] = &[
+    b"M.K.", b"M!K!", b"M&K!", b"N.T.", b"FLT4", b"FLT8", b"CD81", b"OKTA", b"OCTA",
+];
+
+impl FileType {
+    /// Detects the type of a file from its contents, given at least its
+    /// first few kilobytes.
+    ///
+    /// Returns `None` if the contents match none of the supported formats.
+    pub fn detect(data: &[u8]) -> Option<FileType> {
+        match id3v2_len(data) {
+            Some(len) => detect_after_id3v2(data.get(len..).unwrap_or_default()),
+            None => detect_header(data),
+        }
+    }
+
+    /// Detects the type of the file at `path` from its contents, like
+    /// `detect()`.
+    pub fn detect_file<P: AsRef<Path>>(path: P) -> Result<Option<FileType>, FileError> {
+        let path = path.as_ref();
+        let io_err = |e| FileError::from_io(path, e);
+        let mut file = fs::File::open(path).map_err(io_err)?;
+        let header = read_header(&mut file).map_err(io_err)?;
+        match id3v2_len(&header) {
+            Some(len) => {
+                file.seek(SeekFrom::Start(len as u64)).map_err(io_err)?;
+                let header = read_header(&mut file).map_err(io_err)?;
+                Ok(detect_after_id3v2(&header))
+            }
+            None => Ok(detect_header(&header)),
+        }
+    }
+}
+
+fn read_header(file: &mut fs::File) -> io::Result<Vec<u8>> {
+    let mut header = Vec::new();
+    file.take(HEADER_LEN).read_to_end(&mut header)?;
+    Ok(header)
+}
+
+// the length of the ID3v2 tag at the start of `data`, if any
+fn id3v2_len(data: &[u8]) -> Option<usize> {
+    if data.len() < 10 || !data.starts_with(b"ID3") {
+        return None;
+    }
+    let size = &data[6..10];
+    if size.iter().any(|b| b & 0x80 != 0) {
+        return None;
+    }
+    let size = size.iter().fold(0, |n, b| n << 7 | *b as usize);
+    let footer = if data[5] & 0x10 != 0 { 10 } else { 0 };
+    Some(10 + size + footer)
+}
+
+// TagLib skips an ID3v2 tag in front of MPEG, FLAC, APE and TrueAudio files,
+// so the format is told by what follows it
+fn detect_after_id3v2(data: &[u8]) -> Option<FileType> {
+    detect_header(data)
+}
+
+fn detect_header(data: &[u8]) -> Option<FileType> {
+    let at = |offset: usize, magic: &[u8]| {
+        data.get(offset..offset + magic.len()) == Some(magic)
+    };
+
+    if at(0, b"fLaC") {
+        Some(FileType::FLAC)
+    } else if at(0, b"OggS") {
+        detect_ogg(data)
+    } else if at(4, b"ftyp") {
+        Some(FileType::MP4)
+    } else if at(0, ASF_GUID) {
+        Some(FileType::ASF)
+    } else if at(0, b"FORM") && (at(8, b"AIFF") || at(8, b"AIFC")) {
+        Some(FileType::AIFF)
+    } else if at(0, b"RIFF") && at(8, b"WAVE") {
+        Some(FileType::WAV)
+    } else if at(0, b"MAC ") {
+        Some(FileType::APE)
+    } else if at(0, b"wvpk") {
+        Some(FileType::WavPack)
+    } else if at(0, b"TTA1") {
+        Some(FileType::TrueAudio)
+    } else if at(0, b"MPCK") || at(0, b"MP+") {
+        Some(FileType::MPC)
+    } else if at(0, b"DSD ") {
+        Some(FileType::DSF)
+    } else if at(0, b"FRM8") && at(12, b"DSD ") {
+        Some(FileType::DFF)
+    } else if at(0, b"IMPM") {
+        Some(FileType::IT)
+    } else if at(0, b"Extended Module: ") {
+        Some(FileType::XM)
+    } else if at(44, b"SCRM") {
+        Some(FileType::S3M)
+    } else if data.get(1080..1084).is_some_and(is_mod_signature) {
+        Some(FileType::MOD)
+    } else if is_mpeg_audio(data) {
+        Some(FileType::MPEG)
+    } else {
+        None
+    }
+}
+
+// Ogg files are told apart by the codec header in the first packet
+fn detect_ogg(data: &[u8]) -> Option<FileType> {
+    let segments = *data.get(26)? as usize;
+    let packet = data.get(27 + segments..)?;
+    if packet.starts_with(b"\x01vorbis") {
+        Some(FileType::OggVorbis)
+    } else if packet.starts_with(b"OpusHead") {
+        Some(FileType::OPUS)
+    } else if packet.starts_with(b"Speex   ") {
+        Some(FileType::Speex)
+    } else if packet.starts_with(b"\x7fFLAC") || packet.starts_with(b"fLaC") {
+        Some(FileType::OggFlac)
+    } else {
+        None
+    }
+}
+
+// e.g. "M.K.", "6CHN" or "16CH"
+fn is_mod_signature(sig: &[u8]) -> bool {
+    MOD_SIGNATURES.contains(&sig)
+        || (sig[0].is_ascii_digit() && &sig[1..] == b"CHN")
+        || (sig[..2].iter().all(u8::is_ascii_digit) && &sig[2..] == b"CH")
+}
+
+// The fields of an MPEG audio frame header that stay the same from one frame
+// to the next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct MpegFormat {
+    // 1 for MPEG-1, 2 for MPEG-2 and 25 for MPEG-2.5
+    version: u8,
+    layer: u8,
+    samplerate: u32,
+}
+
+// Whether `data` starts with two consecutive MPEG audio frames of the same
+// format; a frame sync alone is too common in other data to go by.
+fn is_mpeg_audio(data: &[u8]) -> bool {
+    let (format, len) = match mpeg_frame(data) {
+        Some(frame) => frame,
+        None => return false,
+    };
+    matches!(data.get(len..).and_then(mpeg_frame), Some((next, _)) if next == format)
+}
+
+// the format and length of the MPEG audio frame whose header starts `data`
+fn mpeg_frame(data: &[u8]) -> Option<(MpegFormat, usize)> {
+    const BITRATES_V1: [[u32; 15]; 3] = [
+        [0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448],
+        [0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384],
+        [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320],
+    ];
+    const BITRATES_V2: [[u32; 15]; 3] = [
+        [0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256],
+        [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+        [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+    ];
+    const SAMPLERATES_V1: [u32; 3] = [44100, 48000, 32000];
+
+    let header = data.get(..4)?;
+    if header[0] != 0xff || header[1] & 0xe0 != 0xe0 {
+        return None;
+    }
+    // version 0b01 and layer 0b00 are reserved; the latter is also what
+    // ADTS streams use
+    let version = match (header[1] >> 3) & 0x03 {
+        0b00 => 25,
+        0b10 => 2,
+        0b11 => 1,
+        _ => return None,
+    };
+    let layer = match (header[1] >> 1) & 0x03 {
+        0b01 => 3,
+        0b10 => 2,
+        0b11 => 1,
+        _ => return None,
+    };
+    // free format frames have no fixed length
+    let bitrate_index = (header[2] >> 4) as usize;
+    if bitrate_index == 0 || bitrate_index == 15 {
+        return None;
+    }
+    let bitrates = if version == 1 { &BITRATES_V1 } else { &BITRATES_V2 };
+    let bitrate = bitrates[layer as usize - 1][bitrate_index] * 1000;
+    let samplerate = *SAMPLERATES_V1.get(((header[2] >> 2) & 0x03) as usize)?;
+    let samplerate = match version {
+        1 => samplerate,
+        2 => samplerate / 2,
+        _ => samplerate / 4,
+    };
+    let padding = ((header[2] >> 1) & 0x01) as u32;
+
+    let len = match layer {
+        1 => (12 * bitrate / samplerate + padding) * 4,
+        3 if version != 1 => 72 * bitrate / samplerate + padding,
+        _ => 144 * bitrate / samplerate + padding,
+    };
+    Some((MpegFormat { version, layer, samplerate }, len as usize))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ogg(packet: &[u8]) -> Vec<u8> {
+        let mut data = b"OggS\x00\x02".to_vec();
+        data.resize(26, 0);
+        data.push(1);
+        data.push(packet.len() as u8);
+        data.extend_from_slice(packet);
+        data
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(FileType::detect(b"fLaC\x00\x00\x00\x22"), Some(FileType::FLAC));
+        assert_eq!(FileType::detect(b"\x00\x00\x00\x20ftypM4A "), Some(FileType::MP4));
+        assert_eq!(FileType::detect(b"RIFF\x24\x00\x00\x00WAVEfmt "), Some(FileType::WAV));
+        assert_eq!(FileType::detect(b"FORM\x00\x00\x00\x00AIFC"), Some(FileType::AIFF));
+        assert_eq!(FileType::detect(b"FRM8\x00\x00\x00\x00\x00\x00\x00\x00DSD "),
+                   Some(FileType::DFF));
+        assert_eq!(FileType::detect(b"DSD \x1c\x00\x00\x00"), Some(FileType::DSF));
+        assert_eq!(FileType::detect(ASF_GUID), Some(FileType::ASF));
+        assert_eq!(FileType::detect(b"Extended Module: Song"), Some(FileType::XM));
+        assert_eq!(FileType::detect(b""), None);
+        assert_eq!(FileType::detect(b"not an audio file"), None);
+        assert_eq!(FileType::detect(b"FORM\x00\x00\x00\x00ILBM"), None);
+    }
+
+    #[test]
+    fn test_detect_ogg() {
+        assert_eq!(FileType::detect(&ogg(b"\x01vorbis\x00")), Some(FileType::OggVorbis));
+        assert_eq!(FileType::detect(&ogg(b"OpusHead\x01")), Some(FileType::OPUS));
+        assert_eq!(FileType::detect(&ogg(b"Speex   1.2")), Some(FileType::Speex));
+        assert_eq!(FileType::detect(&ogg(b"\x7fFLAC\x01\x00")), Some(FileType::OggFlac));
+        assert_eq!(FileType::detect(&ogg(b"\x80theora")), None);
+        assert_eq!(FileType::detect(b"OggS"), None);
+    }
+
+    #[test]
+    fn test_detect_tracker() {
+        let mut data = vec![0; 1084];
+        data[1080..].copy_from_slice(b"M.K.");
+        assert_eq!(FileType::detect(&data), Some(FileType::MOD));
+        data[1080..].copy_from_slice(b"12CH");
+        assert_eq!(FileType::detect(&data), Some(FileType::MOD));
+        data[1080..].copy_from_slice(b"ABCD");
+        assert_eq!(FileType::detect(&data), None);
+        data[44..48].copy_from_slice(b"SCRM");
+        assert_eq!(FileType::detect(&data), Some(FileType::S3M));
+        assert_eq!(FileType::detect(b"IMPMSong"), Some(FileType::IT));
+    }
+
+    // `count` MPEG-1 Layer III frames of 128 kbit/s at 44.1 kHz
+    fn mpeg_frames(count: usize) -> Vec<u8> {
+        let mut frame = b"\xff\xfb\x90\x00".to_vec();
+        frame.resize(417, 0);
+        frame.repeat(count)
+    }
+
+    #[test]
+    fn test_detect_mpeg() {
+        assert_eq!(FileType::detect(&mpeg_frames(2)), Some(FileType::MPEG));
+
+        // a frame sync is not enough without a second frame after it
+        assert_eq!(FileType::detect(&mpeg_frames(1)), None);
+        assert_eq!(FileType::detect(b"\xff\xfb\x90\x00"), None);
+        let mut data = mpeg_frames(2);
+        data[417 + 1] = 0xf3;
+        assert_eq!(FileType::detect(&data), None);
+
+        // reserved version and layer bits, the latter also used by ADTS
+        let mut data = mpeg_frames(2);
+        data[1] = 0xeb;
+        data[417 + 1] = 0xeb;
+        assert_eq!(FileType::detect(&data), None);
+        data[1] = 0xf1;
+        data[417 + 1] = 0xf1;
+        assert_eq!(FileType::detect(&data), None);
+
+        // MPEG-2 Layer III frames hold half as many samples
+        let mut data = b"\xff\xf3\x80\x00".to_vec();
+        data.resize(208, 0);
+        assert_eq!(FileType::detect(&data.repeat(2)), Some(FileType::MPEG));
+    }
+
+    #[test]
+    fn test_detect_id3v2() {
+        let mut data = b"ID3\x04\x00\x00\x00\x00\x01\x00".to_vec();
+        data.resize(10 + 128, 0);
+        data.extend_from_slice(b"fLaC");
+        assert_eq!(FileType::detect(&data), Some(FileType::FLAC));
+
+        // what follows the tag has to be MPEG audio as well
+        data.truncate(10 + 128);
+        assert_eq!(FileType::detect(&data), None);
+        data.extend_from_slice(b"not audio");
+        assert_eq!(FileType::detect(&data), None);
+        data.truncate(10 + 128);
+        data.extend_from_slice(&mpeg_frames(2));
+        assert_eq!(FileType::detect(&data), Some(FileType::MPEG));
+    }
+
+    #[test]
+    fn test_detect_file() {
+        assert_eq!(FileType::detect_file("fixtures/test.mp3").unwrap(), Some(FileType::MPEG));
+        assert_eq!(FileType::detect_file("fixtures/test.flac").unwrap(), Some(FileType::FLAC));
+        assert_eq!(FileType::detect_file("fixtures/pic.jpg").unwrap(), None);
+        assert!(matches!(FileType::detect_file("fixtures/missing.mp3"),
+                         Err(FileError::NotFound { .. })));
+    }
+}
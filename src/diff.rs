@@ -0,0 +1,389 @@
+// Copyright 2015  Emmanuele Bassi. All rights reserved.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::{FileError, Metadata, PictureInfo, PropertyMap, TagInfo, TagMut};
+
+/// A change of a value between two snapshots.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Change<T> {
+    /// The value was added
+    Added(T),
+    /// The value was removed
+    Removed(T),
+    /// The value was changed from `old` to `new`
+    Changed { old: T, new: T },
+}
+
+impl<T> Change<T> {
+    /// Returns the value before the change, if any.
+    pub fn old_value(&self) -> Option<&T> {
+        match self {
+            Change::Added(_) => None,
+            Change::Removed(old) | Change::Changed { old, .. } => Some(old),
+        }
+    }
+
+    /// Returns the value after the change, if any.
+    pub fn new_value(&self) -> Option<&T> {
+        match self {
+            Change::Added(new) | Change::Changed { new, .. } => Some(new),
+            Change::Removed(_) => None,
+        }
+    }
+}
+
+/// A basic tag field, as found in `taglib::TagInfo`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TagField {
+    Title,
+    Artist,
+    Album,
+    Comment,
+    Genre,
+    Year,
+    Track,
+}
+
+impl fmt::Display for TagField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TagField::Title => "title",
+            TagField::Artist => "artist",
+            TagField::Album => "album",
+            TagField::Comment => "comment",
+            TagField::Genre => "genre",
+            TagField::Year => "year",
+            TagField::Track => "track",
+        })
+    }
+}
+
+/// The value of a basic tag field.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TagValue {
+    /// The value of a text field, such as the title
+    Text(String),
+    /// The value of a numeric field, the year or the track number
+    Number(u32),
+}
+
+impl fmt::Display for TagValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TagValue::Text(s) => f.write_str(s),
+            TagValue::Number(n) => n.fmt(f),
+        }
+    }
+}
+
+/// The changes between two `Metadata` snapshots, as computed by
+/// `Metadata::diff()` and applied by `taglib::File::apply()`.
+///
+/// Audio properties are not part of the diff, as they cannot be changed.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MetadataDiff {
+    /// The changed basic tag fields
+    pub tag: BTreeMap<TagField, Change<TagValue>>,
+    /// The changed properties, by key
+    pub properties: BTreeMap<String, Change<Vec<String>>>,
+    /// The change of the embedded pictures, which are compared as a whole
+    ///
+    /// A picture without image data keeps the data of the picture at the
+    /// same position.
+    pub pictures: Option<Change<Vec<PictureInfo>>>,
+}
+
+impl MetadataDiff {
+    /// Returns whether there are no changes.
+    pub fn is_empty(&self) -> bool {
+        self.tag.is_empty() && self.properties.is_empty() && self.pictures.is_none()
+    }
+}
+
+impl Metadata {
+    /// Returns the changes that turn `self` into `other`.
+    ///
+    /// Pictures of `other` without image data keep the data of the picture
+    /// at the same position in `self`, and are equal to it if their
+    /// descriptions match.  Properties or pictures missing from either
+    /// snapshot are left unchanged.
+    pub fn diff(&self, other: &Metadata) -> MetadataDiff {
+        let properties = match (&self.properties, &other.properties) {
+            (Some(old), Some(new)) => diff_maps(old, new),
+            _ => BTreeMap::new(),
+        };
+        let pictures = match (&self.pictures, &other.pictures) {
+            (Some(old), Some(new)) => diff_pictures(old, new),
+            _ => None,
+        };
+
+        MetadataDiff {
+            tag: diff_maps(&tag_values(self.tag.as_ref()), &tag_values(other.tag.as_ref())),
+            properties,
+            pictures,
+        }
+    }
+}
+
+fn diff_pictures(old: &[PictureInfo], new: &[PictureInfo]) -> Option<Change<Vec<PictureInfo>>> {
+    let new = fill_picture_data(new, old);
+    if old.len() == new.len() && old.iter().zip(&new).all(|(o, n)| o.same_as(n)) {
+        return None;
+    }
+    match (old.is_empty(), new.is_empty()) {
+        (true, _) => Some(Change::Added(new)),
+        (_, true) => Some(Change::Removed(old.to_vec())),
+        _ => Some(Change::Changed { old: old.to_vec(), new }),
+    }
+}
+
+// Gives the pictures without image data, which was left out rather than
+// removed, the data of the picture at the same position in `old`.
+pub(crate) fn fill_picture_data(pictures: &[PictureInfo], old: &[PictureInfo]) -> Vec<PictureInfo> {
+    pictures.iter()
+        .enumerate()
+        .map(|(i, p)| match old.get(i) {
+            Some(o) if p.data.is_none() => PictureInfo { data: o.data.clone(), ..p.clone() },
+            _ => p.clone(),
+        })
+        .collect()
+}
+
+fn tag_values(tag: Option<&TagInfo>) -> BTreeMap<TagField, TagValue> {
+    let mut values = BTreeMap::new();
+    if let Some(t) = tag {
+        let text = [
+            (TagField::Title, &t.title),
+            (TagField::Artist, &t.artist),
+            (TagField::Album, &t.album),
+            (TagField::Comment, &t.comment),
+            (TagField::Genre, &t.genre),
+        ];
+        for (field, value) in text {
+            if let Some(v) = value {
+                values.insert(field, TagValue::Text(v.clone()));
+            }
+        }
+        for (field, value) in [(TagField::Year, t.year), (TagField::Track, t.track)] {
+            if let Some(v) = value {
+                values.insert(field, TagValue::Number(v));
+            }
+        }
+    }
+    values
+}
+
+fn diff_maps<K, V>(old: &BTreeMap<K, V>, new: &BTreeMap<K, V>) -> BTreeMap<K, Change<V>>
+    where K: Ord + Clone, V: PartialEq + Clone
+{
+    let mut res = BTreeMap::new();
+    for (k, v) in old {
+        match new.get(k) {
+            None => {
+                res.insert(k.clone(), Change::Removed(v.clone()));
+            }
+            Some(n) if n != v => {
+                res.insert(k.clone(), Change::Changed { old: v.clone(), new: n.clone() });
+            }
+            Some(_) => {}
+        }
+    }
+    for (k, v) in new {
+        if !old.contains_key(k) {
+            res.insert(k.clone(), Change::Added(v.clone()));
+        }
+    }
+    res
+}
+
+// Sets `field` to `value`, or clears it
+pub(crate) fn set_tag_field(tag: &mut TagMut<'_>,
+                            field: TagField,
+                            value: Option<&TagValue>) -> Result<(), FileError> {
+    match field {
+        TagField::Title => tag.set_title(text_value(value)?),
+        TagField::Artist => tag.set_artist(text_value(value)?),
+        TagField::Album => tag.set_album(text_value(value)?),
+        TagField::Comment => tag.set_comment(text_value(value)?),
+        TagField::Genre => tag.set_genre(text_value(value)?),
+        TagField::Year => {
+            tag.set_year(number_value(value)?);
+            Ok(())
+        }
+        TagField::Track => {
+            tag.set_track(number_value(value)?);
+            Ok(())
+        }
+    }
+}
+
+fn text_value(value: Option<&TagValue>) -> Result<&str, FileError> {
+    match value {
+        None => Ok(""),
+        Some(TagValue::Text(s)) => Ok(s),
+        Some(v) => Err(FileError::InvalidValue(v.to_string())),
+    }
+}
+
+fn number_value(value: Option<&TagValue>) -> Result<u32, FileError> {
+    match value {
+        None => Ok(0),
+        Some(TagValue::Number(n)) => Ok(*n),
+        Some(v) => Err(FileError::InvalidValue(v.to_string())),
+    }
+}
+
+// Applies the property changes to `properties`
+pub(crate) fn patch_properties(properties: &mut PropertyMap,
+                               changes: &BTreeMap<String, Change<Vec<String>>>) {
+    for (key, change) in changes {
+        match change.new_value() {
+            Some(values) => {
+                properties.insert(key.clone(), values.clone());
+            }
+            None => {
+                properties.remove(key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::{Picture, PictureType};
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    fn metadata() -> Metadata {
+        let mut properties = PropertyMap::new();
+        properties.insert("ARTIST".to_owned(), strings(&["Artist"]));
+        properties.insert("DATE".to_owned(), strings(&["2000"]));
+        Metadata {
+            tag: Some(TagInfo {
+                artist: Some("Artist".to_owned()),
+                year: Some(2000),
+                ..TagInfo::default()
+            }),
+            properties: Some(properties),
+            pictures: Some(Vec::new()),
+            ..Metadata::default()
+        }
+    }
+
+    #[test]
+    fn test_diff_equal() {
+        assert!(metadata().diff(&metadata()).is_empty());
+        assert!(Metadata::default().diff(&Metadata::default()).is_empty());
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = metadata();
+        let mut new = metadata();
+        let tag = new.tag.as_mut().unwrap();
+        tag.artist = None;
+        tag.title = Some("Title".to_owned());
+        tag.year = Some(2001);
+        let properties = new.properties.as_mut().unwrap();
+        properties.remove("ARTIST");
+        properties.insert("DATE".to_owned(), strings(&["2001"]));
+        properties.insert("GENRE".to_owned(), strings(&["Jazz", "Rock"]));
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.tag.len(), 3);
+        assert_eq!(diff.tag[&TagField::Artist], Change::Removed(TagValue::Text("Artist".to_owned())));
+        assert_eq!(diff.tag[&TagField::Title], Change::Added(TagValue::Text("Title".to_owned())));
+        assert_eq!(diff.tag[&TagField::Year],
+                   Change::Changed { old: TagValue::Number(2000), new: TagValue::Number(2001) });
+        assert_eq!(diff.properties.len(), 3);
+        assert_eq!(diff.properties["ARTIST"], Change::Removed(strings(&["Artist"])));
+        assert_eq!(diff.properties["GENRE"], Change::Added(strings(&["Jazz", "Rock"])));
+        assert_eq!(diff.properties["DATE"].old_value(), Some(&strings(&["2000"])));
+        assert_eq!(diff.properties["DATE"].new_value(), Some(&strings(&["2001"])));
+        assert_eq!(diff.pictures, None);
+
+        let mut properties = old.properties.clone().unwrap();
+        patch_properties(&mut properties, &diff.properties);
+        assert_eq!(Some(properties), new.properties);
+
+        // properties that could not be read are left alone
+        let unknown = Metadata { properties: None, ..metadata() };
+        assert!(unknown.diff(&new).properties.is_empty());
+        assert!(new.diff(&unknown).properties.is_empty());
+    }
+
+    #[test]
+    fn test_diff_pictures() {
+        let old = metadata();
+        let mut new = metadata();
+        let picture = PictureInfo::from(Picture {
+            mime_type: "image/png".to_owned(),
+            description: String::new(),
+            picture_type: PictureType::FrontCover,
+            data: b"\x89PNG".to_vec(),
+        });
+        new.pictures = Some(vec![picture.clone()]);
+        assert_eq!(old.diff(&new).pictures, Some(Change::Added(vec![picture.clone()])));
+        assert_eq!(new.diff(&old).pictures, Some(Change::Removed(vec![picture.clone()])));
+
+        let mut other = new.clone();
+        other.pictures.as_mut().unwrap()[0].picture_type = PictureType::BackCover;
+        let diff = new.diff(&other);
+        assert!(diff.tag.is_empty() && diff.properties.is_empty());
+        assert_eq!(diff.pictures.unwrap().new_value(), other.pictures.as_ref());
+
+        // pictures without data keep theirs
+        other.pictures.as_mut().unwrap()[0].data = None;
+        let diff = new.diff(&other);
+        other.pictures.as_mut().unwrap()[0].data = picture.data.clone();
+        assert_eq!(diff.pictures.unwrap().new_value(), other.pictures.as_ref());
+
+        // and equal pictures with data if their descriptions match
+        let described = Metadata { pictures: Some(vec![picture.clone().without_data()]), ..metadata() };
+        assert_eq!(new.diff(&described).pictures, None);
+        assert_eq!(described.diff(&new).pictures, None);
+        let mut resized = described.clone();
+        resized.pictures.as_mut().unwrap()[0].size = 5;
+        assert!(described.diff(&resized).pictures.is_some());
+
+        // pictures that could not be read are left alone
+        let unknown = Metadata { pictures: None, ..metadata() };
+        assert_eq!(unknown.diff(&new).pictures, None);
+        assert_eq!(new.diff(&unknown).pictures, None);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(TagField::Track.to_string(), "track");
+        assert_eq!(TagValue::Number(3).to_string(), "3");
+        assert_eq!(TagValue::Text("Title".to_owned()).to_string(), "Title");
+    }
+}
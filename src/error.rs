@@ -0,0 +1,254 @@
+// Copyright 2015  Emmanuele Bassi. All rights reserved.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::Utf8Error;
+
+use super::FileType;
+
+/// Errors returned when opening or modifying a `taglib::File`.
+#[derive(Debug)]
+pub enum FileError {
+    /// The file does not exist
+    NotFound { path: PathBuf, source: io::Error },
+    /// Permission to read the file was denied
+    PermissionDenied { path: PathBuf, source: io::Error },
+    /// The file could not be accessed for another reason
+    Io { path: Option<PathBuf>, source: io::Error },
+    /// The file name is invalid
+    InvalidFileName { path: PathBuf },
+    /// The file is not in a format supported by TagLib
+    UnsupportedFormat { path: Option<PathBuf> },
+    /// The file is in a supported format, but is corrupt or invalid
+    CorruptFile { path: Option<PathBuf> },
+    /// A property key is invalid, e.g. because it contains a NUL byte or is
+    /// not supported by the file
+    InvalidKey(String),
+    /// A property value is invalid, e.g. because it contains a NUL byte; for
+    /// complex properties, this holds the name of the attribute
+    InvalidValue(String),
+    /// A string read from the file is not valid UTF-8
+    InvalidUtf8(Utf8Error),
+    /// No meta-data is available
+    NoAvailableTag,
+    /// No audio properties are available
+    NoAvailableAudioProperties,
+    /// The changes to the file could not be saved
+    Save(SaveError),
+}
+
+impl FileError {
+    /// Wraps an error returned when accessing `path`.
+    pub(crate) fn from_io(path: &Path, source: io::Error) -> FileError {
+        let path = path.to_path_buf();
+        match source.kind() {
+            io::ErrorKind::NotFound => FileError::NotFound { path, source },
+            io::ErrorKind::PermissionDenied => FileError::PermissionDenied { path, source },
+            _ => FileError::Io { path: Some(path), source },
+        }
+    }
+
+    /// Returns the path of the file the error relates to, if known.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            FileError::NotFound { path, .. }
+            | FileError::PermissionDenied { path, .. }
+            | FileError::InvalidFileName { path } => Some(path),
+            FileError::Io { path, .. }
+            | FileError::UnsupportedFormat { path }
+            | FileError::CorruptFile { path } => path.as_deref(),
+            FileError::Save(e) => e.path(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileError::NotFound { path, .. } => {
+                write!(f, "file not found: {}", path.display())
+            }
+            FileError::PermissionDenied { path, .. } => {
+                write!(f, "permission denied: {}", path.display())
+            }
+            FileError::Io { path: Some(path), source } => {
+                write!(f, "could not access {}: {}", path.display(), source)
+            }
+            FileError::Io { path: None, source } => write!(f, "I/O error: {}", source),
+            FileError::InvalidFileName { path } => {
+                write!(f, "invalid file name: {}", path.display())
+            }
+            FileError::UnsupportedFormat { path: Some(path) } => {
+                write!(f, "unsupported file format: {}", path.display())
+            }
+            FileError::UnsupportedFormat { path: None } => f.write_str("unsupported file format"),
+            FileError::CorruptFile { path: Some(path) } => {
+                write!(f, "corrupt or invalid file: {}", path.display())
+            }
+            FileError::CorruptFile { path: None } => f.write_str("corrupt or invalid file"),
+            FileError::InvalidKey(key) => write!(f, "invalid property key: {:?}", key),
+            FileError::InvalidValue(value) => write!(f, "invalid property value: {:?}", value),
+            FileError::InvalidUtf8(e) => write!(f, "invalid UTF-8 in meta-data: {}", e),
+            FileError::NoAvailableTag => f.write_str("no meta-data available"),
+            FileError::NoAvailableAudioProperties => f.write_str("no audio properties available"),
+            FileError::Save(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for FileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FileError::NotFound { source, .. }
+            | FileError::PermissionDenied { source, .. }
+            | FileError::Io { source, .. } => Some(source),
+            FileError::InvalidUtf8(e) => Some(e),
+            FileError::Save(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<Utf8Error> for FileError {
+    fn from(e: Utf8Error) -> FileError {
+        FileError::InvalidUtf8(e)
+    }
+}
+
+impl From<SaveError> for FileError {
+    fn from(e: SaveError) -> FileError {
+        FileError::Save(e)
+    }
+}
+
+/// Errors returned by `taglib::File::save()`.
+#[derive(Debug)]
+pub enum SaveError {
+    /// The file on disk is read-only
+    ReadOnlyFile { path: PathBuf },
+    /// The file was opened read-only, e.g. because it was read from a
+    /// stream that is not writable
+    OpenedReadOnly { path: Option<PathBuf> },
+    /// There is not enough space left on the device holding the file
+    DiskFull { path: PathBuf },
+    /// TagLib does not support writing files of this type
+    UnsupportedFormat { path: Option<PathBuf>, filetype: FileType },
+    /// TagLib failed to save the file for an unknown reason
+    Failed { path: Option<PathBuf> },
+    /// Reading or writing the file, its copy, or the Rust stream it was
+    /// read from failed
+    Io { path: Option<PathBuf>, source: io::Error },
+    /// The file was not opened from the filesystem, so it cannot be replaced
+    /// by a copy
+    NotOnDisk,
+    /// The copy replacing the file could not be opened or updated, or the
+    /// file could not be opened again once it was replaced
+    Replace { path: PathBuf, source: Box<FileError> },
+}
+
+impl SaveError {
+    /// Returns the path of the file that could not be saved, if known.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            SaveError::ReadOnlyFile { path }
+            | SaveError::DiskFull { path }
+            | SaveError::Replace { path, .. } => Some(path),
+            SaveError::OpenedReadOnly { path }
+            | SaveError::UnsupportedFormat { path, .. }
+            | SaveError::Failed { path }
+            | SaveError::Io { path, .. } => path.as_deref(),
+            SaveError::NotOnDisk => None,
+        }
+    }
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("could not save ")?;
+        match self.path() {
+            Some(path) => write!(f, "{}", path.display())?,
+            None => f.write_str("file")?,
+        }
+        match self {
+            SaveError::ReadOnlyFile { .. } => f.write_str(": file is read-only"),
+            SaveError::OpenedReadOnly { .. } => f.write_str(": file was opened read-only"),
+            SaveError::DiskFull { .. } => f.write_str(": no space left on device"),
+            SaveError::UnsupportedFormat { filetype, .. } => {
+                write!(f, ": writing {} files is not supported", filetype.name())
+            }
+            SaveError::Failed { .. } => f.write_str(": unknown TagLib failure"),
+            SaveError::Io { source, .. } => write!(f, ": {}", source),
+            SaveError::NotOnDisk => f.write_str(": file was not opened from the filesystem"),
+            SaveError::Replace { source, .. } => write!(f, ": could not replace it: {}", source),
+        }
+    }
+}
+
+impl Error for SaveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SaveError::Io { source, .. } => Some(source),
+            SaveError::Replace { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// The error returned when parsing an unknown name of a type of file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseFileTypeError(pub String);
+
+impl fmt::Display for ParseFileTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown file type: {:?}", self.0)
+    }
+}
+
+impl Error for ParseFileTypeError {}
+
+/// The error returned when parsing an unknown name of a picture type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsePictureTypeError(pub String);
+
+impl fmt::Display for ParsePictureTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown picture type: {:?}", self.0)
+    }
+}
+
+impl Error for ParsePictureTypeError {}
+
+/// The error returned when changing a setting of `taglib::config` after a
+/// file was opened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SettingsLockedError;
+
+impl fmt::Display for SettingsLockedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TagLib settings cannot be changed after a file was opened")
+    }
+}
+
+impl Error for SettingsLockedError {}
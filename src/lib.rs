@@ -30,46 +30,157 @@ extern crate windows_sys as windows;
 #[cfg(target_os = "windows")]
 extern crate codepage;
 
-use std::{mem, ptr};
-use std::collections::HashSet;
+use std::{mem, ptr, slice};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryInto;
-use std::ffi::{CStr, CString};
-use std::path::Path;
-use std::str::Utf8Error;
+use std::ffi::{CStr, CString, OsString};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Seek, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::{FromStr, Utf8Error};
+use std::sync::{Once, PoisonError, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 
 use lazy_static::lazy_static;
-use libc::c_char;
+use libc::{c_char, c_int, c_void};
 use sys as ll;
 
-fn c_str_to_str(c_str: *const c_char) -> Option<String> {
+pub mod config;
+mod detect;
+mod diff;
+mod error;
+mod metadata;
+mod picture;
+mod resolver;
+mod stream;
+mod variant;
+
+pub use diff::{Change, MetadataDiff, TagField, TagValue};
+pub use error::{FileError, ParseFileTypeError, ParsePictureTypeError, SaveError,
+                SettingsLockedError};
+pub use metadata::{AudioInfo, Metadata, PictureInfo, TagInfo};
+#[cfg(feature = "serde")]
+pub use metadata::WithoutPictureData;
+pub use picture::{ImageFormat, Picture, PictureType};
+pub use resolver::{FileTypeResolver, ResolverId};
+pub use stream::Truncate;
+use stream::StreamAdapter;
+pub use variant::{RawVariant, Variant};
+
+// Copies a string returned by a taglib_tag_*() getter and frees it, see init()
+fn c_str_to_str_free(c_str: *const c_char) -> Option<String> {
     if c_str.is_null() {
         None
     } else {
-        let bytes = unsafe { CStr::from_ptr(c_str).to_bytes() };
+        let res = match unsafe { c_str_to_string(c_str) } {
+            Ok(s) if s.is_empty() => None,
+            Ok(s) => Some(s),
+            Err(_) => {
+                let bytes = unsafe { CStr::from_ptr(c_str).to_bytes() };
+                Some(String::from_utf8_lossy(bytes).to_string())
+            }
+        };
+        unsafe {
+            ll::taglib_free(c_str as *mut c_void);
+        }
+        res
+    }
+}
+
+// Applies the process-wide TagLib settings this crate relies on, and those
+// chosen in `config`.  Called before a file is created, so before any
+// string is returned by TagLib.  The settings are plain globals in TagLib,
+// so they are only ever changed here, once.
+fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| unsafe {
+        // otherwise TagLib keeps every returned string until
+        // taglib_tag_free_strings(), which frees them for all files at once
+        ll::taglib_set_string_management_enabled(0);
+        config::apply();
+    });
+}
 
-        if bytes.is_empty() { None } else { Some(String::from_utf8_lossy(bytes).to_string()) }
+// Decodes a string returned by TagLib, see config::set_strings_unicode(),
+// which cannot change once a file is open
+unsafe fn c_str_to_string(c_str: *const c_char) -> Result<String, Utf8Error> {
+    if c_str.is_null() {
+        Ok(String::new())
+    } else if config::strings_unicode() {
+        CStr::from_ptr(c_str).to_str().map(ToString::to_string)
+    } else {
+        Ok(CStr::from_ptr(c_str).to_bytes().iter().map(|&b| char::from(b)).collect())
+    }
+}
+
+// Encodes a string for TagLib, see config::set_strings_unicode()
+fn str_to_c_string(s: &str) -> Option<CString> {
+    if config::strings_unicode() {
+        CString::new(s).ok()
+    } else {
+        let latin1 = s.chars().map(|c| u8::try_from(c).ok()).collect::<Option<Vec<u8>>>()?;
+        CString::new(latin1).ok()
     }
 }
 
+fn key_to_c_string(key: &str) -> Result<CString, FileError> {
+    str_to_c_string(key).ok_or_else(|| FileError::InvalidKey(key.to_owned()))
+}
+
+fn value_to_c_string(value: &str) -> Result<CString, FileError> {
+    str_to_c_string(value).ok_or_else(|| FileError::InvalidValue(value.to_owned()))
+}
+
 fn u32_to_option(n: u32) -> Option<u32> {
     if n == 0 { None } else { Some(n) }
 }
 
 /// A representation of an audio file, with meta-data and properties.
+///
+/// A `File` can be moved to another thread, but not shared between threads:
+/// TagLib objects are not safe for concurrent use, even for reading.
 pub struct File {
     raw: *mut ll::TagLib_File,
+    path: Option<PathBuf>,
+    // the stream the file reads from, if any; TagLib does not take
+    // ownership of it
+    stream: *mut ll::TagLib_IOStream,
+    // the Rust stream behind `stream`, for files created by `from_stream()`
+    // and `from_reader()`, or null; freed after `stream`
+    adapter: *mut StreamAdapter,
 }
 
+/// The properties of a file, mapping keys such as "ARTIST" to their values.
+pub type PropertyMap = BTreeMap<String, Vec<String>>;
+
+/// A single value of a complex property, mapping attribute names to values.
+///
+/// For example, a "PICTURE" value has the attributes "data", "mimeType",
+/// "description" and "pictureType".
+pub type ComplexProperty = BTreeMap<String, Variant>;
+
 /// The abstract meta-data container for audio files
 ///
 /// Each `Tag` instance can only be created by the `taglib::File::tag()`
 /// method.
-#[allow(dead_code)]
 pub struct Tag<'a> {
     raw: *mut ll::TagLib_Tag,
     file: &'a File,
 }
 
+/// A mutable view of the meta-data of an audio file
+///
+/// Each `TagMut` instance can only be created by the
+/// `taglib::File::tag_mut()` method, and borrows the file mutably for as
+/// long as it lives.  It dereferences to `Tag` for reading.
+pub struct TagMut<'a> {
+    tag: Tag<'a>,
+}
+
 /// Common audio file properties.
 ///
 /// Instances of `AudioProperties` can only be created through the
@@ -84,76 +195,31 @@ impl<'a> Tag<'a> {
     /// Returns the track name, if any.
     pub fn title(&self) -> Option<String> {
         let res = unsafe { ll::taglib_tag_title(self.raw) };
-        c_str_to_str(res)
-    }
-
-    /// Sets the track name.
-    pub fn set_title(&mut self, title: &str) {
-        let cs = CString::new(title).unwrap();
-        let s = cs.as_ptr();
-        unsafe {
-            ll::taglib_tag_set_title(self.raw, s);
-        }
+        c_str_to_str_free(res)
     }
 
     /// Returns the artist name, if any.
     pub fn artist(&self) -> Option<String> {
         let res = unsafe { ll::taglib_tag_artist(self.raw) };
-        c_str_to_str(res)
-    }
-
-    /// Sets the artist name.
-    pub fn set_artist(&mut self, artist: &str) {
-        let cs = CString::new(artist).unwrap();
-        let s = cs.as_ptr();
-        unsafe {
-            ll::taglib_tag_set_artist(self.raw, s);
-        }
+        c_str_to_str_free(res)
     }
 
     /// Returns the album name, if any.
     pub fn album(&self) -> Option<String> {
         let res = unsafe { ll::taglib_tag_album(self.raw) };
-        c_str_to_str(res)
-    }
-
-    /// Sets the album name.
-    pub fn set_album(&mut self, album: &str) {
-        let cs = CString::new(album).unwrap();
-        let s = cs.as_ptr();
-        unsafe {
-            ll::taglib_tag_set_album(self.raw, s);
-        }
+        c_str_to_str_free(res)
     }
 
     /// Returns the track comment, if any.
     pub fn comment(&self) -> Option<String> {
         let res = unsafe { ll::taglib_tag_comment(self.raw) };
-        c_str_to_str(res)
-    }
-
-    /// Sets the track comment.
-    pub fn set_comment(&mut self, comment: &str) {
-        let cs = CString::new(comment).unwrap();
-        let s = cs.as_ptr();
-        unsafe {
-            ll::taglib_tag_set_comment(self.raw, s);
-        }
+        c_str_to_str_free(res)
     }
 
     /// Returns the genre name, if any.
     pub fn genre(&self) -> Option<String> {
         let res = unsafe { ll::taglib_tag_genre(self.raw) };
-        c_str_to_str(res)
-    }
-
-    /// Sets the genre name.
-    pub fn set_genre(&mut self, genre: &str) {
-        let cs = CString::new(genre).unwrap();
-        let s = cs.as_ptr();
-        unsafe {
-            ll::taglib_tag_set_genre(self.raw, s);
-        }
+        c_str_to_str_free(res)
     }
 
     /// Returns the year, if any.
@@ -161,25 +227,11 @@ impl<'a> Tag<'a> {
         u32_to_option(unsafe { ll::taglib_tag_year(self.raw) as u32 })
     }
 
-    /// Sets the year.
-    pub fn set_year(&mut self, year: u32) {
-        unsafe {
-            ll::taglib_tag_set_year(self.raw, year);
-        }
-    }
-
     /// Returns the track number, if any.
     pub fn track(&self) -> Option<u32> {
         u32_to_option(unsafe { ll::taglib_tag_track(self.raw) as u32 })
     }
 
-    /// Sets the track number.
-    pub fn set_track(&mut self, track: u32) {
-        unsafe {
-            ll::taglib_tag_set_track(self.raw, track);
-        }
-    }
-
     pub fn album_artist(&self) -> Option<String> {
         self.file.album_artist()
     }
@@ -233,12 +285,94 @@ impl<'a> Tag<'a> {
     }
 }
 
+impl<'a> TagMut<'a> {
+    /// Sets the track name.
+    pub fn set_title(&mut self, title: &str) -> Result<(), FileError> {
+        let cs = value_to_c_string(title)?;
+        let s = cs.as_ptr();
+        unsafe {
+            ll::taglib_tag_set_title(self.tag.raw, s);
+        }
+        Ok(())
+    }
+
+    /// Sets the artist name.
+    pub fn set_artist(&mut self, artist: &str) -> Result<(), FileError> {
+        let cs = value_to_c_string(artist)?;
+        let s = cs.as_ptr();
+        unsafe {
+            ll::taglib_tag_set_artist(self.tag.raw, s);
+        }
+        Ok(())
+    }
+
+    /// Sets the album name.
+    pub fn set_album(&mut self, album: &str) -> Result<(), FileError> {
+        let cs = value_to_c_string(album)?;
+        let s = cs.as_ptr();
+        unsafe {
+            ll::taglib_tag_set_album(self.tag.raw, s);
+        }
+        Ok(())
+    }
+
+    /// Sets the track comment.
+    pub fn set_comment(&mut self, comment: &str) -> Result<(), FileError> {
+        let cs = value_to_c_string(comment)?;
+        let s = cs.as_ptr();
+        unsafe {
+            ll::taglib_tag_set_comment(self.tag.raw, s);
+        }
+        Ok(())
+    }
+
+    /// Sets the genre name.
+    pub fn set_genre(&mut self, genre: &str) -> Result<(), FileError> {
+        let cs = value_to_c_string(genre)?;
+        let s = cs.as_ptr();
+        unsafe {
+            ll::taglib_tag_set_genre(self.tag.raw, s);
+        }
+        Ok(())
+    }
+
+    /// Sets the year.
+    pub fn set_year(&mut self, year: u32) {
+        unsafe {
+            ll::taglib_tag_set_year(self.tag.raw, year);
+        }
+    }
+
+    /// Sets the track number.
+    pub fn set_track(&mut self, track: u32) {
+        unsafe {
+            ll::taglib_tag_set_track(self.tag.raw, track);
+        }
+    }
+
+}
+
+impl<'a> Deref for TagMut<'a> {
+    type Target = Tag<'a>;
+
+    fn deref(&self) -> &Tag<'a> {
+        &self.tag
+    }
+}
+
 impl<'a> AudioProperties<'a> {
-    /// Returns the length, in seconds, of the track.
+    /// Returns the length, in whole seconds, of the track.
+    #[deprecated(note = "use `duration()`, which has millisecond precision")]
     pub fn length(&self) -> u32 {
         unsafe { ll::taglib_audioproperties_length(self.raw) as u32 }
     }
 
+    /// Returns the length of the track, with millisecond precision.
+    pub fn duration(&self) -> Duration {
+        let ms = unsafe { ll::taglib_shim_audioproperties_length_ms(self.raw) };
+        Duration::from_millis(ms.try_into().unwrap_or(0))
+    }
+
     /// Returns the most appropriate bit rate for the track, in kB/s.
     /// For constant bit rate formats, the returned value is the bit
     /// rate of the file; for variable bit rate formats this is either
@@ -258,9 +392,8 @@ impl<'a> AudioProperties<'a> {
     }
 }
 
-const MUT_PTR_C_CHAR_LEN: usize = mem::size_of::<*mut c_char>();
-
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FileType {
     /// MPEG file
     MPEG = ll::TAGLIB_FILE_MPEG as isize,
@@ -305,16 +438,16 @@ pub enum FileType {
 }
 
 lazy_static! {
-    static ref MPEG_SUFFIX: Vec<&'static str> = vec![".mp3", ".aac"];
+    static ref MPEG_SUFFIX: Vec<&'static str> = vec![".mp3", ".mp2", ".aac"];
     static ref OGG_VORBIS_SUFFIX: Vec<&'static str> = vec![".ogg"];
     static ref FLAC_SUFFIX: Vec<&'static str> = vec![".flac"];
     static ref MPC_SUFFIX: Vec<&'static str> = vec![".mpc"];
-    static ref OGG_FLAC_SUFFIX: Vec<&'static str> = vec![".flac", ".oga"];
+    static ref OGG_FLAC_SUFFIX: Vec<&'static str> = vec![".oga"];
 
     static ref WAV_PACK_SUFFIX: Vec<&'static str> = vec![".wv"];
     static ref SPEEX_SUFFIX: Vec<&'static str> = vec![".spx"];
     static ref TRUE_AUDIO_SUFFIX: Vec<&'static str> = vec![".tta"];
-    static ref MP4_SUFFIX: Vec<&'static str> = vec![".mp4", ".m4a", "m4b", "m4p", "m4v", "isom", "3g2"];
+    static ref MP4_SUFFIX: Vec<&'static str> = vec![".mp4", ".m4a", ".m4b", ".m4p", ".m4r", ".m4v", ".3g2"];
     static ref ASF_SUFFIX: Vec<&'static str> = vec![".asf", ".wma"];
 
     static ref AIFF_SUFFIX: Vec<&'static str> = vec![".aif", ".aiff", ".aifc"];
@@ -327,7 +460,7 @@ lazy_static! {
     static ref XM_SUFFIX: Vec<&'static str> = vec![".xm"];
     static ref OPUS_SUFFIX: Vec<&'static str> = vec![".opus"];
     static ref DSF_SUFFIX: Vec<&'static str> = vec![".dsf"];
-    static ref DFF_SUFFIX: Vec<&'static str> = vec![".dff"];
+    static ref DFF_SUFFIX: Vec<&'static str> = vec![".dff", ".dsdiff"];
 
     static ref EMPTY_SUFFIX: Vec<&'static str> = vec![];
 
@@ -359,99 +492,159 @@ lazy_static! {
 
         m
     };
+
+    // extensions mapped to types by register_extension(), without the dot
+    static ref REGISTERED_EXTENSIONS: RwLock<HashMap<String, FileType>> =
+        RwLock::new(HashMap::new());
 }
 
 impl FileType {
+    /// Returns all types of file, in the order of their numeric values.
+    pub fn all() -> &'static [FileType] {
+        &[
+            FileType::MPEG,
+            FileType::OggVorbis,
+            FileType::FLAC,
+            FileType::MPC,
+            FileType::OggFlac,
+            FileType::WavPack,
+            FileType::Speex,
+            FileType::TrueAudio,
+            FileType::MP4,
+            FileType::ASF,
+            FileType::AIFF,
+            FileType::WAV,
+            FileType::APE,
+            FileType::IT,
+            FileType::MOD,
+            FileType::S3M,
+            FileType::XM,
+            FileType::OPUS,
+            FileType::DSF,
+            FileType::DFF,
+        ]
+    }
+
+    // the type for a TagLib_File_Type value, if it is a known one
+    fn from_raw(filetype: ll::TagLib_FileType) -> Option<FileType> {
+        match filetype {
+            ll::TAGLIB_FILE_MPEG => Some(FileType::MPEG),
+            ll::TAGLIB_FILE_OGG_VORBIS => Some(FileType::OggVorbis),
+            ll::TAGLIB_FILE_FLAC => Some(FileType::FLAC),
+            ll::TAGLIB_FILE_MPC => Some(FileType::MPC),
+            ll::TAGLIB_FILE_OGG_FLAC => Some(FileType::OggFlac),
+            ll::TAGLIB_FILE_WAV_PACK => Some(FileType::WavPack),
+            ll::TAGLIB_FILE_SPEEX => Some(FileType::Speex),
+            ll::TAGLIB_FILE_TRUE_AUDIO => Some(FileType::TrueAudio),
+            ll::TAGLIB_FILE_MP4 => Some(FileType::MP4),
+            ll::TAGLIB_FILE_ASF => Some(FileType::ASF),
+            ll::TAGLIB_FILE_AIFF => Some(FileType::AIFF),
+            ll::TAGLIB_FILE_WAV => Some(FileType::WAV),
+            ll::TAGLIB_FILE_APE => Some(FileType::APE),
+            ll::TAGLIB_FILE_IT => Some(FileType::IT),
+            ll::TAGLIB_FILE_MOD => Some(FileType::MOD),
+            ll::TAGLIB_FILE_S3M => Some(FileType::S3M),
+            ll::TAGLIB_FILE_XM => Some(FileType::XM),
+            ll::TAGLIB_FILE_OPUS => Some(FileType::OPUS),
+            ll::TAGLIB_FILE_DSF => Some(FileType::DSF),
+            ll::TAGLIB_FILE_DSDIFF => Some(FileType::DFF),
+            _ => None,
+        }
+    }
+
     pub fn name(&self) -> &'static str {
-        if self == &FileType::MPEG {
-            "MPEG"
-        } else if self == &FileType::OggVorbis {
-            "OggVorbis"
-        } else if self == &FileType::FLAC {
-            "FLAC"
-        } else if self == &FileType::MPC {
-            "MPC"
-        } else if self == &FileType::OggFlac {
-            "OggFlac"
-        } else if self == &FileType::WavPack {
-            "WavPack"
-        } else if self == &FileType::Speex {
-            "Speex"
-        } else if self == &FileType::TrueAudio {
-            "TrueAudio"
-        } else if self == &FileType::MP4 {
-            "MP4"
-        } else if self == &FileType::ASF {
-            "ASF"
-        } else if self == &FileType::AIFF {
-            "AIFF"
-        } else if self == &FileType::WAV {
-            "WAV"
-        } else if self == &FileType::APE {
-            "APE"
-        } else if self == &FileType::IT {
-            "IT"
-        } else if self == &FileType::MOD {
-            "MOD"
-        } else if self == &FileType::S3M {
-            "S3M"
-        } else if self == &FileType::XM {
-            "XM"
-        } else if self == &FileType::OPUS {
-            "OPUS"
-        } else if self == &FileType::DSF {
-            "DSF"
-        } else if self == &FileType::DFF {
-            "DFF"
-        } else {
-            ""
+        match self {
+            FileType::MPEG => "MPEG",
+            FileType::OggVorbis => "OggVorbis",
+            FileType::FLAC => "FLAC",
+            FileType::MPC => "MPC",
+            FileType::OggFlac => "OggFlac",
+            FileType::WavPack => "WavPack",
+            FileType::Speex => "Speex",
+            FileType::TrueAudio => "TrueAudio",
+            FileType::MP4 => "MP4",
+            FileType::ASF => "ASF",
+            FileType::AIFF => "AIFF",
+            FileType::WAV => "WAV",
+            FileType::APE => "APE",
+            FileType::IT => "IT",
+            FileType::MOD => "MOD",
+            FileType::S3M => "S3M",
+            FileType::XM => "XM",
+            FileType::OPUS => "OPUS",
+            FileType::DSF => "DSF",
+            FileType::DFF => "DFF",
         }
     }
 
+    /// Returns the extensions TagLib uses to recognize files of this type,
+    /// e.g. ".mp3"; extensions added by `register_extension()` are not
+    /// included.
     pub fn suffix(&self) -> &'static Vec<&str> {
-        if self == &FileType::MPEG {
-            &MPEG_SUFFIX
-        } else if self == &FileType::OggVorbis {
-            &OGG_VORBIS_SUFFIX
-        } else if self == &FileType::FLAC {
-            &FLAC_SUFFIX
-        } else if self == &FileType::MPC {
-            &MPC_SUFFIX
-        } else if self == &FileType::OggFlac {
-            &OGG_FLAC_SUFFIX
-        } else if self == &FileType::WavPack {
-            &WAV_PACK_SUFFIX
-        } else if self == &FileType::Speex {
-            &SPEEX_SUFFIX
-        } else if self == &FileType::TrueAudio {
-            &TRUE_AUDIO_SUFFIX
-        } else if self == &FileType::MP4 {
-            &MP4_SUFFIX
-        } else if self == &FileType::ASF {
-            &ASF_SUFFIX
-        } else if self == &FileType::AIFF {
-            &AIFF_SUFFIX
-        } else if self == &FileType::WAV {
-            &WAV_SUFFIX
-        } else if self == &FileType::APE {
-            &APE_SUFFIX
-        } else if self == &FileType::IT {
-            &IT_SUFFIX
-        } else if self == &FileType::MOD {
-            &MOD_SUFFIX
-        } else if self == &FileType::S3M {
-            &S3M_SUFFIX
-        } else if self == &FileType::XM {
-            &XM_SUFFIX
-        } else if self == &FileType::OPUS {
-            &OPUS_SUFFIX
-        } else if self == &FileType::DSF {
-            &DSF_SUFFIX
-        } else if self == &FileType::DFF {
-            &DSF_SUFFIX
-        } else {
-            &EMPTY_SUFFIX
+        match self {
+            FileType::MPEG => &MPEG_SUFFIX,
+            FileType::OggVorbis => &OGG_VORBIS_SUFFIX,
+            FileType::FLAC => &FLAC_SUFFIX,
+            FileType::MPC => &MPC_SUFFIX,
+            FileType::OggFlac => &OGG_FLAC_SUFFIX,
+            FileType::WavPack => &WAV_PACK_SUFFIX,
+            FileType::Speex => &SPEEX_SUFFIX,
+            FileType::TrueAudio => &TRUE_AUDIO_SUFFIX,
+            FileType::MP4 => &MP4_SUFFIX,
+            FileType::ASF => &ASF_SUFFIX,
+            FileType::AIFF => &AIFF_SUFFIX,
+            FileType::WAV => &WAV_SUFFIX,
+            FileType::APE => &APE_SUFFIX,
+            FileType::IT => &IT_SUFFIX,
+            FileType::MOD => &MOD_SUFFIX,
+            FileType::S3M => &S3M_SUFFIX,
+            FileType::XM => &XM_SUFFIX,
+            FileType::OPUS => &OPUS_SUFFIX,
+            FileType::DSF => &DSF_SUFFIX,
+            FileType::DFF => &DFF_SUFFIX,
+        }
+    }
+
+    /// Returns the type of file with the given extension, with or without
+    /// the leading dot, ignoring case.
+    ///
+    /// Extensions added by `register_extension()` take precedence.
+    pub fn from_extension(ext: &str) -> Option<FileType> {
+        let ext = ext.strip_prefix('.').unwrap_or(ext).to_ascii_lowercase();
+        if let Some(filetype) = registered_type(&ext) {
+            return Some(filetype);
         }
+        FileType::all().iter().find(|t| t.suffix().iter().any(|s| s[1..] == ext)).copied()
+    }
+
+    /// Maps `ext` to `filetype` for `from_extension()` and for opening files
+    /// with `File::new()`, replacing any previous mapping of `ext`.
+    ///
+    /// `File::new()` opens files with a registered extension as `filetype`
+    /// without asking the resolvers added with
+    /// `File::add_file_type_resolver()`.
+    ///
+    /// The extension is given with or without the leading dot and is matched
+    /// ignoring case.
+    pub fn register_extension(ext: &str, filetype: FileType) {
+        let ext = ext.strip_prefix('.').unwrap_or(ext).to_ascii_lowercase();
+        let mut extensions = REGISTERED_EXTENSIONS.write().unwrap_or_else(PoisonError::into_inner);
+        extensions.insert(ext, filetype);
+    }
+
+    /// Removes the mapping of `ext` added by `register_extension()`, if any.
+    pub fn unregister_extension(ext: &str) -> Option<FileType> {
+        let ext = ext.strip_prefix('.').unwrap_or(ext).to_ascii_lowercase();
+        REGISTERED_EXTENSIONS.write().unwrap_or_else(PoisonError::into_inner).remove(&ext)
+    }
+
+    /// Returns whether TagLib can write meta-data to files of this type.
+    ///
+    /// All types can be written, but the tracker module formats (IT, MOD,
+    /// S3M and XM) only keep their title and comment; other changes to them
+    /// are dropped when saving.
+    pub fn supports_writing(&self) -> bool {
+        true
     }
 
     pub fn all_suffix() -> &'static HashSet<&'static str> {
@@ -459,23 +652,45 @@ impl FileType {
     }
 }
 
-#[derive(Debug)]
-pub enum FileError {
-    /// The file is an invalid or an unrecognized audio container
-    InvalidFile,
-    /// The file name is invalid
-    InvalidFileName,
-    /// No meta-data is available
-    NoAvailableTag,
-    /// No audio properties are available
-    NoAvailableAudioProperties,
+impl fmt::Display for FileType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for FileType {
+    type Err = ParseFileTypeError;
+
+    /// Parses the name of a type of file, as returned by `name()`, ignoring
+    /// case.
+    fn from_str(s: &str) -> Result<FileType, ParseFileTypeError> {
+        FileType::all()
+            .iter()
+            .find(|t| t.name().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| ParseFileTypeError(s.to_owned()))
+    }
+}
+
+// the type registered for `ext`, given in lower case without the dot
+fn registered_type(ext: &str) -> Option<FileType> {
+    REGISTERED_EXTENSIONS.read().unwrap_or_else(PoisonError::into_inner).get(ext).copied()
 }
 
+// TagLib objects have no affinity to the thread that created them, and each
+// `File` owns its TagLib file and stream exclusively.  The process-wide
+// settings are only passed to TagLib by init(), before the first file is
+// created, and cannot change afterwards.  The stream adapter requires `Send`.
+unsafe impl Send for File {}
+
 impl Drop for File {
     fn drop(&mut self) {
         unsafe {
-            ll::taglib_tag_free_strings();
             ll::taglib_file_free(self.raw);
+            if !self.stream.is_null() {
+                ll::taglib_iostream_free(self.stream);
+            }
+            StreamAdapter::free(self.adapter);
         }
     }
 }
@@ -487,6 +702,9 @@ const KEY_COPYRIGHT: &'static str = "COPYRIGHT";
 const KEY_LYRICS: &'static str = "LYRICS";
 const KEY_DATE: &'static str = "DATE";
 
+// key for complex property, holds the embedded pictures
+const KEY_PICTURE: &str = "PICTURE";
+
 // key for property, value like 01/02, first is disc_number, last is disc_total
 const KEY_DISC_NUMBER: &'static str = "DISCNUMBER";
 // key for property, value like 01/10, first is track_number, last is track_total
@@ -508,49 +726,225 @@ fn acp_encode(s: &str) -> Option<Vec<u8>> {
     }
 }
 
-#[cfg(not(target_os = "windows"))]
+#[cfg(not(any(unix, target_os = "windows")))]
 fn acp_encode(_s: &str) -> Option<Vec<u8>> {
     None
 }
 
-fn get_filename_c(filename: &str) -> Result<CString, FileError> {
+// on Unix, file names are passed to TagLib as raw bytes, so they don't have
+// to be valid UTF-8
+#[cfg(unix)]
+fn get_filename_c(path: &Path) -> Result<CString, FileError> {
+    use std::os::unix::ffi::OsStrExt;
+
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| FileError::InvalidFileName { path: path.to_path_buf() })
+}
+
+#[cfg(not(unix))]
+fn get_filename_c(path: &Path) -> Result<CString, FileError> {
+    let invalid = || FileError::InvalidFileName { path: path.to_path_buf() };
+    let filename = path.to_str().ok_or_else(invalid)?;
     acp_encode(filename)
         .map_or_else(|| CString::new(filename),
                      |v| {
                          let from_vec = unsafe { CString::from_vec_unchecked(v) };
                          Ok(from_vec)
                      })
-        .map_err(|_| FileError::InvalidFileName)
+        .map_err(|_| invalid())
+}
+
+// TagLib does not report why a file could not be opened, so check that it
+// can be read first
+fn check_readable(path: &Path) -> Result<(), FileError> {
+    fs::File::open(path).map(|_| ()).map_err(|e| FileError::from_io(path, e))
 }
 
 impl File {
     /// Creates a new `taglib::File` for the given `filename`.
+    ///
+    /// TagLib chooses the type of file from its extension, unless the
+    /// extension was mapped to a type by `FileType::register_extension()`,
+    /// which also takes precedence over the resolvers added with
+    /// `add_file_type_resolver()`.
     pub fn new<P: AsRef<Path>>(path: P) -> Result<File, FileError> {
-        let filename = path.as_ref().to_str().ok_or(FileError::InvalidFileName)?;
-        let filename_c = get_filename_c(filename)?;
+        let path = path.as_ref();
+        let registered = path.extension()
+            .and_then(|e| e.to_str())
+            .and_then(|e| registered_type(&e.to_ascii_lowercase()));
+        if let Some(filetype) = registered {
+            return File::new_type(path, filetype);
+        }
+
+        let filename_c = get_filename_c(path)?;
         let filename_c_ptr = filename_c.as_ptr();
+        check_readable(path)?;
 
+        init();
         let f = unsafe { ll::taglib_file_new(filename_c_ptr) };
-        if f.is_null() {
-            return Err(FileError::InvalidFile);
-        }
-
-        Ok(File { raw: f })
+        File::from_raw(f, Some(path.to_path_buf()), ptr::null_mut(), ptr::null_mut())
     }
 
     /// Creates a new `taglib::File` for the given `filename` and type of file.
-    pub fn new_type(filename: &str, filetype: FileType) -> Result<File, FileError> {
-        let filename_c = get_filename_c(filename)?;
+    pub fn new_type<P: AsRef<Path>>(path: P, filetype: FileType) -> Result<File, FileError> {
+        let path = path.as_ref();
+        let filename_c = get_filename_c(path)?;
         let filename_c_ptr = filename_c.as_ptr();
+        check_readable(path)?;
+
+        init();
+        let f = unsafe {
+            ll::taglib_file_new_type(filename_c_ptr, filetype as u32)
+        };
+        File::from_raw(f, Some(path.to_path_buf()), ptr::null_mut(), ptr::null_mut())
+    }
+
+    /// Adds `resolver` to choose the type of files opened with `new()`.
+    ///
+    /// Resolvers are tried before TagLib's own detection, the most recently
+    /// added first, but after the extensions registered with
+    /// `FileType::register_extension()`. Files opened from a stream, from
+    /// bytes or with an explicit type are not affected.
+    ///
+    /// Returns an id to remove the resolver with
+    /// `remove_file_type_resolver()`.
+    pub fn add_file_type_resolver<R: FileTypeResolver + 'static>(resolver: R) -> ResolverId {
+        resolver::add(Box::new(resolver))
+    }
+
+    /// Removes the resolver added with `add_file_type_resolver()` as `id`.
+    ///
+    /// Returns `false` if it was removed already.  Files that are being
+    /// opened with the resolver are waited for.
+    pub fn remove_file_type_resolver(id: ResolverId) -> bool {
+        resolver::remove(id)
+    }
+
+    /// Creates a new `taglib::File` for the given `filename`, choosing the
+    /// type of file from its contents rather than its extension.
+    ///
+    /// Falls back to `new()` if the contents match no supported format.
+    pub fn open_detected<P: AsRef<Path>>(path: P) -> Result<File, FileError> {
+        let path = path.as_ref();
+        match FileType::detect_file(path)? {
+            Some(filetype) => File::new_type(path, filetype),
+            None => File::new(path),
+        }
+    }
+
+    /// Creates a new `taglib::File` reading from an in-memory copy of `data`.
+    ///
+    /// If `filetype` is `None`, the type of file is detected from its content.
+    /// Changes written by `save()` can be retrieved with `bytes()`.
+    ///
+    /// TagLib sizes in-memory streams with a 32-bit integer, so `data` must be
+    /// smaller than 4 GiB; larger buffers are rejected with `FileError::Io`.
+    pub fn from_bytes(data: &[u8], filetype: Option<FileType>) -> Result<File, FileError> {
+        let size = data.len().try_into().map_err(|_| FileError::Io {
+            path: None,
+            source: io::Error::new(io::ErrorKind::InvalidInput, "buffer too large"),
+        })?;
+        let stream = unsafe {
+            ll::taglib_memory_iostream_new(data.as_ptr() as *const c_char, size)
+        };
+        File::from_iostream(stream, ptr::null_mut(), filetype)
+    }
+
+    /// Creates a new `taglib::File` reading from and writing to `stream`.
+    ///
+    /// If `filetype` is `None`, the type of file is detected from its content.
+    /// The stream is kept alive until the file is dropped.
+    pub fn from_stream<S>(stream: S, filetype: Option<FileType>) -> Result<File, FileError>
+        where S: Read + Seek + Write + Truncate + Send + 'static
+    {
+        let adapter = StreamAdapter::read_writer(stream);
+        let stream = unsafe { StreamAdapter::iostream(adapter) };
+        File::from_iostream(stream, adapter, filetype)
+    }
+
+    /// Creates a new read-only `taglib::File` reading from `stream`.
+    ///
+    /// If `filetype` is `None`, the type of file is detected from its content.
+    /// The stream is kept alive until the file is dropped.
+    pub fn from_reader<S>(stream: S, filetype: Option<FileType>) -> Result<File, FileError>
+        where S: Read + Seek + Send + 'static
+    {
+        let adapter = StreamAdapter::reader(stream);
+        let stream = unsafe { StreamAdapter::iostream(adapter) };
+        File::from_iostream(stream, adapter, filetype)
+    }
+
+    fn from_iostream(stream: *mut ll::TagLib_IOStream,
+                     adapter: *mut StreamAdapter,
+                     filetype: Option<FileType>) -> Result<File, FileError> {
+        if stream.is_null() {
+            unsafe {
+                StreamAdapter::free(adapter);
+            }
+            return Err(FileError::UnsupportedFormat { path: None });
+        }
 
+        init();
         let f = unsafe {
-            ll::taglib_file_new_type(filename_c_ptr, (filetype as u32).try_into().unwrap())
+            match filetype {
+                Some(t) => ll::taglib_shim_file_new_iostream_type(stream, t as u32),
+                None => ll::taglib_file_new_iostream(stream),
+            }
         };
+        File::from_raw(f, None, stream, adapter)
+    }
+
+    // Takes ownership of `f`, `stream` and `adapter`, and checks that TagLib
+    // could parse the file.
+    fn from_raw(f: *mut ll::TagLib_File,
+                path: Option<PathBuf>,
+                stream: *mut ll::TagLib_IOStream,
+                adapter: *mut StreamAdapter) -> Result<File, FileError> {
         if f.is_null() {
-            return Err(FileError::InvalidFile);
+            unsafe {
+                if !stream.is_null() {
+                    ll::taglib_iostream_free(stream);
+                }
+                StreamAdapter::free(adapter);
+            }
+            return Err(FileError::UnsupportedFormat { path });
+        }
+
+        // dropping the file frees the stream as well
+        let file = File { raw: f, path, stream, adapter };
+        if unsafe { ll::taglib_shim_file_has_file(file.raw) } == 0 {
+            return Err(FileError::UnsupportedFormat { path: file.path.clone() });
+        }
+        if !file.is_valid() {
+            return Err(FileError::CorruptFile { path: file.path.clone() });
+        }
+        Ok(file)
+    }
+
+    /// Returns the path of the file, if it was opened from the filesystem.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Returns the current contents of a file created with `from_bytes()`,
+    /// including any changes written by `save()`.
+    ///
+    /// Returns `None` for files read from the filesystem or a Rust stream, or if
+    /// TagLib does not report an in-memory stream.
+    pub fn bytes(&self) -> Option<Vec<u8>> {
+        if self.stream.is_null() || !self.adapter.is_null() {
+            return None;
         }
 
-        Ok(File { raw: f })
+        let mut size = 0;
+        let data = unsafe { ll::taglib_shim_memory_iostream_data(self.stream, &mut size) };
+        if data.is_null() {
+            None
+        } else if size == 0 {
+            Some(Vec::new())
+        } else {
+            Some(unsafe { slice::from_raw_parts(data as *const u8, size as usize) }.to_vec())
+        }
     }
 
     /// Returns the `taglib::Tag` instance for the given file.
@@ -567,11 +961,32 @@ impl File {
         }
     }
 
+    /// Returns the meta-data of the file for modification.
+    pub fn tag_mut(&mut self) -> Result<TagMut<'_>, FileError> {
+        Ok(TagMut { tag: self.tag()? })
+    }
+
     /// Returns whether the file is valid.
     pub fn is_valid(&self) -> bool {
         unsafe { ll::taglib_file_is_valid(self.raw) != 0 }
     }
 
+    /// Returns whether the file was opened read-only, in which case changes
+    /// cannot be saved.
+    pub fn is_read_only(&self) -> bool {
+        unsafe { ll::taglib_shim_file_read_only(self.raw) != 0 }
+    }
+
+    /// Returns the type of file TagLib parses the file as, which depends on
+    /// its contents as well as its extension.
+    pub fn file_type(&self) -> Option<FileType> {
+        let mut filetype = 0;
+        if unsafe { ll::taglib_shim_file_type(self.raw, &mut filetype) } == 0 {
+            return None;
+        }
+        FileType::from_raw(filetype)
+    }
+
     /// Returns the `taglib::AudioProperties` instance for the given file.
     pub fn audioproperties(&self) -> Result<AudioProperties<'_>, FileError> {
         let res = unsafe { ll::taglib_file_audioproperties(self.raw) };
@@ -586,72 +1001,154 @@ impl File {
         }
     }
 
+    /// Returns an owned copy of the meta-data and audio properties of the
+    /// file.
+    ///
+    /// Pictures are only described, without their image data; see
+    /// `snapshot_with_picture_data()`.  Parts that cannot be read are left
+    /// out, see `Metadata`.
+    pub fn snapshot(&self) -> Metadata {
+        self.take_snapshot(false)
+    }
+
+    /// Returns an owned copy of the meta-data and audio properties of the
+    /// file, including the image data of the pictures.
+    pub fn snapshot_with_picture_data(&self) -> Metadata {
+        self.take_snapshot(true)
+    }
+
+    fn take_snapshot(&self, picture_data: bool) -> Metadata {
+        let pictures = self.pictures().ok().map(|pictures| {
+            pictures.into_iter()
+                .map(PictureInfo::from)
+                .map(|p| if picture_data { p } else { p.without_data() })
+                .collect()
+        });
+        Metadata {
+            tag: self.tag().ok().map(|t| TagInfo::from(&t)),
+            properties: self.properties().ok(),
+            pictures,
+            audio: self.audioproperties().ok().map(|p| AudioInfo::from(&p)),
+        }
+    }
+
+    /// Returns the changes that turn the meta-data of the file into
+    /// `metadata`.
+    pub fn diff(&self, metadata: &Metadata) -> MetadataDiff {
+        self.snapshot().diff(metadata)
+    }
+
+    /// Applies `diff`, leaving meta-data it does not mention unchanged.
+    ///
+    /// Returns the properties that the file does not support and that were
+    /// not set.
+    pub fn apply(&mut self, diff: &MetadataDiff) -> Result<PropertyMap, FileError> {
+        // checked first, so that nothing is changed if there is no data for
+        // a picture
+        let pictures = match diff.pictures.as_ref().map(Change::new_value) {
+            Some(Some(pictures)) => {
+                let old = self.pictures()?.into_iter().map(PictureInfo::from).collect::<Vec<_>>();
+                let pictures = diff::fill_picture_data(pictures, &old)
+                    .iter()
+                    .map(PictureInfo::to_picture)
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| FileError::InvalidValue("data".to_owned()))?;
+                Some(Some(pictures))
+            }
+            Some(None) => Some(None),
+            None => None,
+        };
+
+        if !diff.tag.is_empty() {
+            let mut tag = self.tag_mut()?;
+            for (field, change) in &diff.tag {
+                diff::set_tag_field(&mut tag, *field, change.new_value())?;
+            }
+        }
+
+        // after the tag fields, as properties such as "TRACKNUMBER" may hold
+        // more than the corresponding field
+        let mut rejected = PropertyMap::new();
+        if !diff.properties.is_empty() {
+            let mut properties = self.properties()?;
+            diff::patch_properties(&mut properties, &diff.properties);
+            rejected = self.set_properties(&properties)?;
+        }
+
+        match pictures {
+            Some(Some(pictures)) => self.set_pictures(&pictures)?,
+            Some(None) => self.remove_pictures()?,
+            None => {}
+        }
+        Ok(rejected)
+    }
+
     pub fn album_artist(&self) -> Option<String> {
-        self.get_first_property(KEY_ALBUM_ARTIST)
+        self.first_property(KEY_ALBUM_ARTIST)
     }
 
-    pub fn set_album_artist(&mut self, value: &str) {
-        self.set_property(KEY_ALBUM_ARTIST, value);
+    pub fn set_album_artist(&mut self, value: &str) -> Result<(), FileError> {
+        self.set_property(KEY_ALBUM_ARTIST, value)
     }
 
-    pub fn remove_album_artist(&mut self) {
-        self.remove_property(KEY_ALBUM_ARTIST);
+    pub fn remove_album_artist(&mut self) -> Result<(), FileError> {
+        self.remove_property(KEY_ALBUM_ARTIST)
     }
 
     pub fn composer(&self) -> Option<String> {
-        self.get_first_property(KEY_COMPOSER)
+        self.first_property(KEY_COMPOSER)
     }
 
-    pub fn set_composer(&mut self, value: &str) {
-        self.set_property(KEY_COMPOSER, value);
+    pub fn set_composer(&mut self, value: &str) -> Result<(), FileError> {
+        self.set_property(KEY_COMPOSER, value)
     }
 
-    pub fn remove_composer(&mut self) {
-        self.remove_property(KEY_COMPOSER);
+    pub fn remove_composer(&mut self) -> Result<(), FileError> {
+        self.remove_property(KEY_COMPOSER)
     }
 
     pub fn copyright(&self) -> Option<String> {
-        self.get_first_property(KEY_COPYRIGHT)
+        self.first_property(KEY_COPYRIGHT)
     }
 
-    pub fn set_copyright(&mut self, value: &str) {
-        self.set_property(KEY_COPYRIGHT, value);
+    pub fn set_copyright(&mut self, value: &str) -> Result<(), FileError> {
+        self.set_property(KEY_COPYRIGHT, value)
     }
 
-    pub fn remove_copyright(&mut self) {
-        self.remove_property(KEY_COPYRIGHT);
+    pub fn remove_copyright(&mut self) -> Result<(), FileError> {
+        self.remove_property(KEY_COPYRIGHT)
     }
 
     pub fn lyrics(&self) -> Option<String> {
-        self.get_first_property(KEY_LYRICS)
+        self.first_property(KEY_LYRICS)
     }
 
-    pub fn set_lyrics(&mut self, value: &str) {
-        self.set_property(KEY_LYRICS, value);
+    pub fn set_lyrics(&mut self, value: &str) -> Result<(), FileError> {
+        self.set_property(KEY_LYRICS, value)
     }
 
-    pub fn remove_lyrics(&mut self) {
-        self.remove_property(KEY_LYRICS);
+    pub fn remove_lyrics(&mut self) -> Result<(), FileError> {
+        self.remove_property(KEY_LYRICS)
     }
 
     pub fn date(&self) -> Option<String> {
-        self.get_first_property(KEY_DATE)
+        self.first_property(KEY_DATE)
     }
 
-    pub fn set_date(&mut self, value: &str) {
-        self.set_property(KEY_DATE, value);
+    pub fn set_date(&mut self, value: &str) -> Result<(), FileError> {
+        self.set_property(KEY_DATE, value)
     }
 
-    pub fn remove_date(&mut self) {
-        self.remove_property(KEY_DATE);
+    pub fn remove_date(&mut self) -> Result<(), FileError> {
+        self.remove_property(KEY_DATE)
     }
 
     pub fn track_number(&self) -> Option<u32> {
-        self.tag().unwrap().track()
+        self.tag().ok()?.track()
     }
 
     pub fn track_number_string(&self) -> Option<String> {
-        if let Some(track) = self.tag().unwrap().track() {
+        if let Some(track) = self.track_number() {
             if let Some(track_string_from_prop) = self.track_number_string_from_prop() {
                 if let Some(track_from_prop) = track_string_from_prop.parse::<u32>().ok() {
                     if track_from_prop == track {
@@ -664,43 +1161,42 @@ impl File {
         None
     }
 
-    pub fn set_track_number(&mut self, value: u32, padding: usize) {
-        let t = self.tag().unwrap();
+    pub fn set_track_number(&mut self, value: u32, padding: usize) -> Result<(), FileError> {
+        let t = self.tag()?.raw;
         unsafe {
-            ll::taglib_tag_set_track(t.raw, value);
+            ll::taglib_tag_set_track(t, value);
         }
 
-        if let Some(track_total_string) = self.get_first_property(KEY_TRACK_TOTAL) {
+        if let Some(track_total_string) = self.first_property(KEY_TRACK_TOTAL) {
             if let Some(track_total) = track_total_string.parse::<u32>().ok() {
                 self.set_property(KEY_TRACK_TOTAL,
-                                  &decimal_to_padding_string(track_total, padding));
-                self.set_property_split_num(KEY_TRACK_NUMBER,
-                                            &Some(value),
-                                            &Some(track_total),
-                                            padding);
-                return;
+                                  &decimal_to_padding_string(track_total, padding))?;
+                return self.set_property_split_num(KEY_TRACK_NUMBER,
+                                                   &Some(value),
+                                                   &Some(track_total),
+                                                   padding);
             }
         }
 
         let track_total = self.track_total_from_prop_track_number();
         if let Some(t) = track_total {
-            self.set_property(KEY_TRACK_TOTAL, &decimal_to_padding_string(t, padding));
+            self.set_property(KEY_TRACK_TOTAL, &decimal_to_padding_string(t, padding))?;
         }
-        self.set_property_split_num(KEY_TRACK_NUMBER, &Some(value), &track_total, padding);
+        self.set_property_split_num(KEY_TRACK_NUMBER, &Some(value), &track_total, padding)
     }
 
-    pub fn remove_track_number(&mut self) {
-        let t = self.tag().unwrap();
+    pub fn remove_track_number(&mut self) -> Result<(), FileError> {
+        let t = self.tag()?.raw;
         unsafe {
-            ll::taglib_tag_set_track(t.raw, 0);
+            ll::taglib_tag_set_track(t, 0);
         }
 
         let track_total = self.track_total_string();
-        self.set_property_split_text(KEY_TRACK_NUMBER, &None, &track_total);
+        self.set_property_split_text(KEY_TRACK_NUMBER, &None, &track_total)
     }
 
     pub fn track_total(&self) -> Option<u32> {
-        if let Some(track_total_string) = self.get_first_property(KEY_TRACK_TOTAL) {
+        if let Some(track_total_string) = self.first_property(KEY_TRACK_TOTAL) {
             let track_total = track_total_string.parse::<u32>().ok();
             if track_total.is_some() {
                 return track_total;
@@ -710,7 +1206,7 @@ impl File {
     }
 
     pub fn track_total_string(&self) -> Option<String> {
-        let track_total = self.get_first_property(KEY_TRACK_TOTAL);
+        let track_total = self.first_property(KEY_TRACK_TOTAL);
         if track_total.is_some() {
             track_total
         } else {
@@ -718,18 +1214,18 @@ impl File {
         }
     }
 
-    pub fn set_track_total(&mut self, value: u32, padding: usize) {
-        self.set_property(KEY_TRACK_TOTAL, &decimal_to_padding_string(value, padding));
+    pub fn set_track_total(&mut self, value: u32, padding: usize) -> Result<(), FileError> {
+        self.set_property(KEY_TRACK_TOTAL, &decimal_to_padding_string(value, padding))?;
 
         let track_number = self.track_number_from_prop();
-        self.set_property_split_num(KEY_TRACK_NUMBER, &track_number, &Some(value), padding);
+        self.set_property_split_num(KEY_TRACK_NUMBER, &track_number, &Some(value), padding)
     }
 
-    pub fn remove_track_total(&mut self) {
-        self.remove_property(KEY_TRACK_TOTAL);
+    pub fn remove_track_total(&mut self) -> Result<(), FileError> {
+        self.remove_property(KEY_TRACK_TOTAL)?;
 
         let track_number = self.track_number_string();
-        self.set_property_split_text(KEY_TRACK_NUMBER, &track_number, &None);
+        self.set_property_split_text(KEY_TRACK_NUMBER, &track_number, &None)
     }
 
     fn track_number_from_prop(&mut self) -> Option<u32> {
@@ -762,14 +1258,14 @@ impl File {
         disc_number
     }
 
-    pub fn set_disc_number(&mut self, value: u32, padding: usize) {
+    pub fn set_disc_number(&mut self, value: u32, padding: usize) -> Result<(), FileError> {
         let disc_total = self.disc_total();
-        self.set_property_split_num(KEY_DISC_NUMBER, &Some(value), &disc_total, padding);
+        self.set_property_split_num(KEY_DISC_NUMBER, &Some(value), &disc_total, padding)
     }
 
-    pub fn remove_disc_number(&mut self) {
+    pub fn remove_disc_number(&mut self) -> Result<(), FileError> {
         let (_, disc_total) = self.text_pair_by_key(KEY_DISC_NUMBER);
-        self.set_property_split_text(KEY_DISC_NUMBER, &None, &disc_total);
+        self.set_property_split_text(KEY_DISC_NUMBER, &None, &disc_total)
     }
 
     pub fn disc_total(&self) -> Option<u32> {
@@ -782,39 +1278,41 @@ impl File {
         disc_total
     }
 
-    pub fn set_disc_total(&mut self, total_disc: u32, padding: usize) {
+    pub fn set_disc_total(&mut self, total_disc: u32, padding: usize) -> Result<(), FileError> {
         let disc_number = self.disc_number();
-        self.set_property_split_num(KEY_DISC_NUMBER, &disc_number, &Some(total_disc), padding);
+        self.set_property_split_num(KEY_DISC_NUMBER, &disc_number, &Some(total_disc), padding)
     }
 
-    pub fn remove_disc_total(&mut self) {
+    pub fn remove_disc_total(&mut self) -> Result<(), FileError> {
         let (disc_number, _) = self.text_pair_by_key(KEY_DISC_NUMBER);
-        self.set_property_split_text(KEY_DISC_NUMBER, &disc_number, &None);
+        self.set_property_split_text(KEY_DISC_NUMBER, &disc_number, &None)
     }
 
     fn set_property_split_text(&mut self,
                                key: &str,
                                first: &Option<String>,
-                               last: &Option<String>) {
-        self.remove_property(key);
+                               last: &Option<String>) -> Result<(), FileError> {
+        self.remove_property(key)?;
         if let Some(ref value) = text_pair_to_string(first, last) {
-            self.set_property(key, value);
+            self.set_property(key, value)?;
         }
+        Ok(())
     }
 
     fn set_property_split_num(&mut self,
                               key: &str,
                               first: &Option<u32>,
                               last: &Option<u32>,
-                              padding: usize) {
-        self.remove_property(key);
+                              padding: usize) -> Result<(), FileError> {
+        self.remove_property(key)?;
         if let Some(ref value) = num_pair_to_string(first, last, padding) {
-            self.set_property(key, value);
+            self.set_property(key, value)?;
         }
+        Ok(())
     }
 
     fn number_pair_by_key(&self, key: &str) -> (Option<u32>, Option<u32>) {
-        if let Some(ref text) = self.get_first_property(key) {
+        if let Some(ref text) = self.first_property(key) {
             get_number_pair(text)
         } else {
             (None, None)
@@ -822,113 +1320,485 @@ impl File {
     }
 
     fn text_pair_by_key(&self, key: &str) -> (Option<String>, Option<String>) {
-        if let Some(ref text) = self.get_first_property(key) {
+        if let Some(ref text) = self.first_property(key) {
             get_text_pair(text)
         } else {
             (None, None)
         }
     }
 
-    pub fn get_first_property(&self, key: &str) -> Option<String> {
-        let vec = self.get_property(key).ok()?;
-        if !vec.is_empty() {
-            Some(vec.first().unwrap().clone())
-        } else {
-            None
-        }
+    pub fn get_first_property(&self, key: &str) -> Result<Option<String>, FileError> {
+        Ok(self.get_property(key)?.into_iter().next())
+    }
+
+    // for the well-known keys used by the accessors above, which are valid
+    fn first_property(&self, key: &str) -> Option<String> {
+        self.get_first_property(key).ok().flatten()
     }
 
-    pub fn get_property(&self, key: &str) -> Result<Vec<String>, Utf8Error> {
-        let cs = CString::new(key).unwrap();
+    pub fn get_property(&self, key: &str) -> Result<Vec<String>, FileError> {
+        let cs = key_to_c_string(key)?;
         let s = cs.as_ptr();
         let call_res = unsafe {
             ll::taglib_property_get(self.raw, s)
         };
-        c_char_to_vec_string_free(call_res)
+        Ok(c_char_to_vec_string_free(call_res)?)
     }
 
-    pub fn keys(&self) -> Result<Vec<String>, Utf8Error> {
+    pub fn keys(&self) -> Result<Vec<String>, FileError> {
         let call_res = unsafe {
             ll::taglib_property_keys(self.raw)
         };
-        c_char_to_vec_string_free(call_res)
+        Ok(c_char_to_vec_string_free(call_res)?)
     }
 
-    pub fn set_property(&mut self, key: &str, value: &str) {
-        let cs = CString::new(key).unwrap();
+    pub fn set_property(&mut self, key: &str, value: &str) -> Result<(), FileError> {
+        let cs = key_to_c_string(key)?;
         let s = cs.as_ptr();
 
-        let vs = CString::new(value).unwrap();
+        let vs = value_to_c_string(value)?;
         let v = vs.as_ptr();
         unsafe {
             ll::taglib_property_set(self.raw, s, v);
         }
+        Ok(())
     }
 
-    pub fn set_append_property(&mut self, key: &str, value: &str) {
-        let cs = CString::new(key).unwrap();
+    pub fn set_append_property(&mut self, key: &str, value: &str) -> Result<(), FileError> {
+        let cs = key_to_c_string(key)?;
         let s = cs.as_ptr();
 
-        let vs = CString::new(value).unwrap();
+        let vs = value_to_c_string(value)?;
         let v = vs.as_ptr();
         unsafe {
             ll::taglib_property_set_append(self.raw, s, v);
         }
+        Ok(())
     }
 
-    pub fn remove_property(&mut self, key: &str) {
-        let cs = CString::new(key).unwrap();
+    pub fn remove_property(&mut self, key: &str) -> Result<(), FileError> {
+        let cs = key_to_c_string(key)?;
         let s = cs.as_ptr();
         unsafe {
             ll::taglib_property_set(self.raw, s, ptr::null());
         }
+        Ok(())
     }
 
-    /// Updates the meta-data of the file.
-    pub fn save(&self) -> bool {
-        unsafe { ll::taglib_file_save(self.raw) != 0 }
+    /// Returns all properties of the file, such as "ARTIST" or "TITLE".
+    pub fn properties(&self) -> Result<PropertyMap, FileError> {
+        let call_res = unsafe {
+            ll::taglib_shim_property_map(self.raw, config::strings_unicode() as ll::TagLib_Bool)
+        };
+        unsafe {
+            let res = convert_property_map(call_res);
+            ll::taglib_shim_property_map_free(call_res);
+            Ok(res?)
+        }
     }
-}
 
-fn text_pair_to_string(first: &Option<String>, last: &Option<String>) -> Option<String> {
-    match (first, last) {
-        (None, None) => None,
-        (None, Some(b)) => Some("/".to_owned() + b),
-        (Some(a), None) => Some(a.to_owned()),
-        (Some(a), Some(b)) => Some(a.to_owned() + "/" + b),
+    /// Replaces all properties of the file with `properties`, removing those
+    /// that are missing from `properties`.
+    ///
+    /// Returns the properties that the file does not support and that were
+    /// not set.
+    pub fn set_properties(&mut self, properties: &PropertyMap) -> Result<PropertyMap, FileError> {
+        let raw = RawPropertyMap::new(properties)?;
+        let call_res = unsafe {
+            ll::taglib_shim_set_property_map(self.raw,
+                                             raw.as_ptr(),
+                                             config::strings_unicode() as ll::TagLib_Bool)
+        };
+        unsafe {
+            let res = convert_property_map(call_res);
+            ll::taglib_shim_property_map_free(call_res);
+            Ok(res?)
+        }
     }
-}
 
-fn num_pair_to_string(first: &Option<u32>,
-                      second: &Option<u32>,
-                      padding: usize) -> Option<String> {
-    match (first, second) {
-        (None, None) => None,
-        (None, Some(b)) => Some("/".to_owned() + &decimal_to_padding_string(*b, padding)),
-        (Some(a), None) => Some(decimal_to_padding_string(*a, padding)),
-        (Some(a), Some(b)) => {
-            Some(decimal_to_padding_string(*a, padding) + "/"
-                + &decimal_to_padding_string(*b, padding))
+    /// Returns identifiers of the meta-data that TagLib cannot represent as
+    /// properties and keeps as is, such as unknown ID3v2 frames or binary
+    /// APE items.
+    pub fn unsupported_properties(&self) -> Result<Vec<String>, FileError> {
+        let call_res = unsafe {
+            ll::taglib_shim_unsupported_properties(self.raw,
+                                                   config::strings_unicode() as ll::TagLib_Bool)
+        };
+        unsafe {
+            let res = convert_double_pointer_to_vec(call_res);
+            ll::taglib_shim_string_list_free(call_res);
+            Ok(res?)
         }
     }
-}
 
-fn get_text_pair(text: &str) -> (Option<String>, Option<String>) {
-    let mut split = text.split('/');
-    let first = get_text(&split.next());
-    let second = get_text(&split.next());
-    (first, second)
-}
+    /// Removes the meta-data identified by `properties`, as returned by
+    /// `unsupported_properties()`.
+    pub fn remove_unsupported_properties<S>(&mut self, properties: &[S]) -> Result<(), FileError>
+        where S: AsRef<str>
+    {
+        let strings = properties.iter()
+            .map(|p| key_to_c_string(p.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut ptrs = strings.iter().map(|cs| cs.as_ptr()).collect::<Vec<_>>();
+        ptrs.push(ptr::null());
+        unsafe {
+            ll::taglib_shim_remove_unsupported_properties(self.raw,
+                                                          ptrs.as_ptr(),
+                                                          config::strings_unicode()
+                                                              as ll::TagLib_Bool);
+        }
+        Ok(())
+    }
 
-fn get_text(input: &Option<&str>) -> Option<String> {
-    if let Some(string) = input {
-        let string = string.trim();
-        if !string.is_empty() {
-            return Some(string.to_owned());
+    /// Returns the keys of the complex properties of the file, such as
+    /// "PICTURE" or "GENERALOBJECT".
+    pub fn complex_property_keys(&self) -> Result<Vec<String>, FileError> {
+        let call_res = unsafe {
+            ll::taglib_complex_property_keys(self.raw)
+        };
+        if call_res.is_null() {
+            Ok(Vec::new())
+        } else {
+            unsafe {
+                let res = convert_double_pointer_to_vec(call_res);
+                ll::taglib_complex_property_free_keys(call_res);
+                Ok(res?)
+            }
         }
     }
-    None
-}
+
+    /// Returns all values of the complex property `key`.
+    pub fn complex_property(&self, key: &str) -> Result<Vec<ComplexProperty>, FileError> {
+        let cs = key_to_c_string(key)?;
+        let s = cs.as_ptr();
+        let call_res = unsafe {
+            ll::taglib_complex_property_get(self.raw, s)
+        };
+        if call_res.is_null() {
+            Ok(Vec::new())
+        } else {
+            unsafe {
+                let res = convert_complex_property(call_res);
+                ll::taglib_complex_property_free(call_res);
+                Ok(res?)
+            }
+        }
+    }
+
+    /// Replaces all values of the complex property `key` with `value`.
+    ///
+    /// Fails with `FileError::InvalidKey` if the file does not support `key`.
+    pub fn set_complex_property(&mut self,
+                                key: &str,
+                                value: &ComplexProperty) -> Result<(), FileError> {
+        let cs = key_to_c_string(key)?;
+        let s = cs.as_ptr();
+        let mut raw = RawComplexProperty::new(value)?;
+        let res = unsafe { ll::taglib_complex_property_set(self.raw, s, raw.as_mut_ptr()) };
+        complex_property_result(key, res)
+    }
+
+    /// Replaces all values of the complex property `key` with `values`, at
+    /// once: if any value is invalid, the property is left unchanged.
+    ///
+    /// Fails with `FileError::InvalidKey` if the file does not support `key`.
+    pub fn set_complex_property_values(&mut self,
+                                       key: &str,
+                                       values: &[ComplexProperty]) -> Result<(), FileError> {
+        let cs = key_to_c_string(key)?;
+        let raw = values.iter().map(RawComplexProperty::new).collect::<Result<Vec<_>, _>>()?;
+        let mut ptrs = raw.iter().map(RawComplexProperty::as_ptr).collect::<Vec<_>>();
+        ptrs.push(ptr::null());
+        let res = unsafe {
+            ll::taglib_shim_complex_property_set_list(self.raw, cs.as_ptr(), ptrs.as_ptr(),
+                                                      config::strings_unicode() as ll::TagLib_Bool)
+        };
+        complex_property_result(key, res)
+    }
+
+    /// Appends `value` to the complex property `key`.
+    ///
+    /// Fails with `FileError::InvalidKey` if the file does not support `key`.
+    pub fn append_complex_property(&mut self,
+                                   key: &str,
+                                   value: &ComplexProperty) -> Result<(), FileError> {
+        let cs = key_to_c_string(key)?;
+        let s = cs.as_ptr();
+        let mut raw = RawComplexProperty::new(value)?;
+        let res = unsafe {
+            ll::taglib_complex_property_set_append(self.raw, s, raw.as_mut_ptr())
+        };
+        complex_property_result(key, res)
+    }
+
+    /// Removes all values of the complex property `key`.
+    pub fn remove_complex_property(&mut self, key: &str) -> Result<(), FileError> {
+        let cs = key_to_c_string(key)?;
+        let s = cs.as_ptr();
+        let res = unsafe { ll::taglib_complex_property_set(self.raw, s, ptr::null_mut()) };
+        complex_property_result(key, res)
+    }
+
+    /// Returns the pictures embedded in the file, such as the cover art.
+    ///
+    /// Values of the "PICTURE" complex property without image data are
+    /// skipped; `complex_property()` returns them as well.
+    pub fn pictures(&self) -> Result<Vec<Picture>, FileError> {
+        let cs = key_to_c_string(KEY_PICTURE)?;
+        let s = cs.as_ptr();
+        let call_res = unsafe {
+            ll::taglib_complex_property_get(self.raw, s)
+        };
+        if call_res.is_null() {
+            return Ok(Vec::new());
+        }
+
+        let res = unsafe { convert_pictures(call_res) };
+        unsafe {
+            ll::taglib_complex_property_free(call_res);
+        }
+        res
+    }
+
+    /// Replaces all pictures embedded in the file with `pictures`, at once.
+    pub fn set_pictures(&mut self, pictures: &[Picture]) -> Result<(), FileError> {
+        let values = pictures.iter().map(ComplexProperty::from).collect::<Vec<_>>();
+        self.set_complex_property_values(KEY_PICTURE, &values)
+    }
+
+    /// Embeds `picture` in the file, after any existing pictures.
+    pub fn add_picture(&mut self, picture: &Picture) -> Result<(), FileError> {
+        self.append_complex_property(KEY_PICTURE, &picture.into())
+    }
+
+    /// Removes all pictures embedded in the file.
+    pub fn remove_pictures(&mut self) -> Result<(), FileError> {
+        self.remove_complex_property(KEY_PICTURE)
+    }
+
+    /// Updates the meta-data of the file.
+    pub fn save(&mut self) -> Result<(), SaveError> {
+        // TagLib does not report all stream failures, so check for them
+        // even if it claims success
+        unsafe { StreamAdapter::take_error(self.adapter) };
+        let mut errno = 0;
+        let saved = unsafe { ll::taglib_shim_file_save(self.raw, &mut errno) } != 0;
+        if let Some(source) = unsafe { StreamAdapter::take_error(self.adapter) } {
+            return Err(SaveError::Io { path: None, source });
+        }
+        // only writes to files opened by name go through the C library
+        if let Some(err) = self.path.as_ref().and_then(|path| write_error(path, errno)) {
+            return Err(err);
+        }
+        if saved {
+            Ok(())
+        } else {
+            Err(self.save_error())
+        }
+    }
+
+    /// Updates the meta-data of the file without ever leaving it partly
+    /// written: the changes are saved to a copy of the file, which then
+    /// replaces it.
+    ///
+    /// The file keeps its permissions and, on Unix, its owner.  If saving
+    /// fails, the file on disk is unchanged and the changes stay pending.
+    /// Afterwards, the file is read again from its path; if that fails,
+    /// `SaveError::Replace` is returned although the changes were saved.
+    pub fn save_atomic(&mut self) -> Result<(), SaveError> {
+        let path = self.path.clone().ok_or(SaveError::NotOnDisk)?;
+        let filetype = self.file_type().ok_or_else(|| SaveError::Replace {
+            path: path.clone(),
+            source: Box::new(FileError::UnsupportedFormat { path: Some(path.clone()) }),
+        })?;
+        let temp = temp_path(&path);
+        let res = self.save_copy(&path, &temp, filetype);
+        if res.is_err() {
+            let _ = fs::remove_file(&temp);
+            return res;
+        }
+        sync_parent(&path);
+
+        *self = File::new_type(&path, filetype).map_err(|e| replace_error(&path, e))?;
+        Ok(())
+    }
+
+    // Saves the file to a copy at `temp`, which then replaces `path`.
+    fn save_copy(&self, path: &Path, temp: &Path, filetype: FileType) -> Result<(), SaveError> {
+        copy_file(path, temp)?;
+        let mut copy = File::new_type(temp, filetype).map_err(|e| replace_error(path, e))?;
+        self.replay_edits(&mut copy).map_err(|e| replace_error(path, e))?;
+        copy.save()?;
+        // TagLib writes through a buffer, which closing the copy flushes
+        drop(copy);
+
+        let file = fs::OpenOptions::new().write(true).open(temp);
+        file.and_then(|f| f.sync_all()).map_err(|e| save_io_error(temp, e))?;
+        fs::rename(temp, path).map_err(|e| save_io_error(path, e))
+    }
+
+    // Gives `copy`, a fresh copy of the file, the meta-data of the file,
+    // including the changes that are not saved yet.
+    fn replay_edits(&self, copy: &mut File) -> Result<(), FileError> {
+        let kept = self.unsupported_properties()?;
+        let removed = copy.unsupported_properties()?
+            .into_iter()
+            .filter(|p| !kept.contains(p))
+            .collect::<Vec<_>>();
+        if !removed.is_empty() {
+            copy.remove_unsupported_properties(&removed)?;
+        }
+
+        copy.set_properties(&self.properties()?)?;
+
+        let mut keys = self.complex_property_keys()?;
+        keys.extend(copy.complex_property_keys()?);
+        keys.sort();
+        keys.dedup();
+        for key in &keys {
+            copy.set_complex_property_values(key, &self.complex_property(key)?)?;
+        }
+        Ok(())
+    }
+
+    // TagLib does not report why saving failed, so check the likely causes
+    fn save_error(&self) -> SaveError {
+        let path = self.path.clone();
+        if self.is_read_only() {
+            if let Some(path) = path.as_ref() {
+                let res = fs::OpenOptions::new().write(true).open(path);
+                if matches!(res, Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied) {
+                    return SaveError::ReadOnlyFile { path: path.clone() };
+                }
+            }
+            return SaveError::OpenedReadOnly { path };
+        }
+
+        if let Some(filetype) = self.file_type() {
+            if !filetype.supports_writing() {
+                return SaveError::UnsupportedFormat { path, filetype };
+            }
+        }
+
+        SaveError::Failed { path }
+    }
+}
+
+// the error for writes to `path` that failed with `errno`, if it is one that
+// keeps the file from being saved
+fn write_error(path: &Path, errno: c_int) -> Option<SaveError> {
+    let path = path.to_path_buf();
+    match errno {
+        libc::ENOSPC => Some(SaveError::DiskFull { path }),
+        libc::EROFS | libc::EACCES => Some(SaveError::ReadOnlyFile { path }),
+        _ => None,
+    }
+}
+
+// a path for a copy of `path` in the same directory and with the same
+// extension, which TagLib may need to detect the type of the file
+fn temp_path(path: &Path) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let mut name = OsString::from(format!(".{}-{}.", process::id(), count));
+    name.push(path.file_name().unwrap_or_default());
+    path.with_file_name(name)
+}
+
+fn save_io_error(path: &Path, source: io::Error) -> SaveError {
+    SaveError::Io { path: Some(path.to_path_buf()), source }
+}
+
+fn replace_error(path: &Path, source: FileError) -> SaveError {
+    SaveError::Replace { path: path.to_path_buf(), source: Box::new(source) }
+}
+
+// copies `path` to the new file `temp`, with the same permissions and, on
+// Unix, owner
+fn copy_file(path: &Path, temp: &Path) -> Result<(), SaveError> {
+    // like `save()`, refuse to replace a file that is read-only
+    let mut src = match fs::OpenOptions::new().read(true).write(true).open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            return Err(SaveError::ReadOnlyFile { path: path.to_path_buf() });
+        }
+        Err(e) => return Err(save_io_error(path, e)),
+    };
+    let original = src.metadata().map_err(|e| save_io_error(path, e))?;
+    let temp_err = |e| save_io_error(temp, e);
+    let mut dst = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(temp)
+        .map_err(temp_err)?;
+    io::copy(&mut src, &mut dst).map_err(temp_err)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{fchown, MetadataExt};
+
+        let owner = dst.metadata().map_err(temp_err)?;
+        if owner.uid() != original.uid() || owner.gid() != original.gid() {
+            fchown(&dst, Some(original.uid()), Some(original.gid())).map_err(temp_err)?;
+        }
+    }
+    dst.set_permissions(original.permissions()).map_err(temp_err)?;
+    dst.sync_all().map_err(temp_err)
+}
+
+// makes the rename of a file in the directory of `path` durable; not all
+// filesystems support this, so errors are ignored
+#[cfg(unix)]
+fn sync_parent(path: &Path) {
+    let parent = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    let _ = fs::File::open(parent).and_then(|d| d.sync_all());
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) {}
+
+fn text_pair_to_string(first: &Option<String>, last: &Option<String>) -> Option<String> {
+    match (first, last) {
+        (None, None) => None,
+        (None, Some(b)) => Some("/".to_owned() + b),
+        (Some(a), None) => Some(a.to_owned()),
+        (Some(a), Some(b)) => Some(a.to_owned() + "/" + b),
+    }
+}
+
+fn num_pair_to_string(first: &Option<u32>,
+                      second: &Option<u32>,
+                      padding: usize) -> Option<String> {
+    match (first, second) {
+        (None, None) => None,
+        (None, Some(b)) => Some("/".to_owned() + &decimal_to_padding_string(*b, padding)),
+        (Some(a), None) => Some(decimal_to_padding_string(*a, padding)),
+        (Some(a), Some(b)) => {
+            Some(decimal_to_padding_string(*a, padding) + "/"
+                + &decimal_to_padding_string(*b, padding))
+        }
+    }
+}
+
+fn get_text_pair(text: &str) -> (Option<String>, Option<String>) {
+    let mut split = text.split('/');
+    let first = get_text(&split.next());
+    let second = get_text(&split.next());
+    (first, second)
+}
+
+fn get_text(input: &Option<&str>) -> Option<String> {
+    if let Some(string) = input {
+        let string = string.trim();
+        if !string.is_empty() {
+            return Some(string.to_owned());
+        }
+    }
+    None
+}
 
 fn get_number_pair(text: &str) -> (Option<u32>, Option<u32>) {
     let mut split = text.split('/');
@@ -960,15 +1830,148 @@ fn c_char_to_vec_string_free(ptr: *mut *mut c_char) -> Result<Vec<String>, Utf8E
 unsafe fn convert_double_pointer_to_vec(data: *mut *mut c_char) -> Result<Vec<String>, Utf8Error> {
     let mut p = data;
     let mut res: Vec<String> = vec![];
-    while p.as_ref().unwrap().as_ref().is_some() {
-        let ele = CStr::from_ptr(p.as_ref().unwrap().as_ref().unwrap())
-            .to_str().map(ToString::to_string)?;
-        res.push(ele);
-        p = p.byte_offset(MUT_PTR_C_CHAR_LEN as isize);
+    while !(*p).is_null() {
+        res.push(c_str_to_string(*p)?);
+        p = p.add(1);
+    }
+    Ok(res)
+}
+
+fn complex_property_result(key: &str, res: ll::TagLib_Bool) -> Result<(), FileError> {
+    if res != 0 {
+        Ok(())
+    } else {
+        Err(FileError::InvalidKey(key.to_owned()))
+    }
+}
+
+unsafe fn convert_property_map(data: *mut *mut *mut c_char) -> Result<PropertyMap, Utf8Error> {
+    let mut p = data;
+    let mut res = PropertyMap::new();
+    while !(*p).is_null() {
+        let key = c_str_to_string(**p)?;
+        let values = convert_double_pointer_to_vec((*p).add(1))?;
+        res.insert(key, values);
+        p = p.add(1);
+    }
+    Ok(res)
+}
+
+unsafe fn convert_complex_property(data: *mut *mut *mut ll::TagLib_Complex_Property_Attribute)
+                                   -> Result<Vec<ComplexProperty>, Utf8Error> {
+    let mut p = data;
+    let mut res: Vec<ComplexProperty> = vec![];
+    while !(*p).is_null() {
+        let mut attr = *p;
+        let mut value = ComplexProperty::new();
+        while !(*attr).is_null() {
+            let a = &**attr;
+            value.insert(c_str_to_string(a.key)?, Variant::from_raw(&a.value)?);
+            attr = attr.add(1);
+        }
+        res.push(value);
+        p = p.add(1);
+    }
+    Ok(res)
+}
+
+unsafe fn convert_pictures(data: *mut *mut *mut ll::TagLib_Complex_Property_Attribute)
+                           -> Result<Vec<Picture>, FileError> {
+    let mut p = data;
+    let mut res: Vec<Picture> = vec![];
+    while !(*p).is_null() {
+        // taglib_picture_from_complex_property() only extracts the first
+        // picture, so hand it one value at a time; values without data are
+        // not pictures, see File::pictures()
+        let mut single = [*p, ptr::null_mut()];
+        let mut raw: ll::TagLib_Complex_Property_Picture_Data = mem::zeroed();
+        ll::taglib_picture_from_complex_property(single.as_mut_ptr(), &mut raw);
+        if !raw.data.is_null() {
+            res.push(Picture::from_raw(&raw)?);
+        }
+        p = p.add(1);
     }
     Ok(res)
 }
 
+/// A NULL terminated array of complex property attributes, together with
+/// the data they point to.
+struct RawComplexProperty {
+    _keys: Vec<CString>,
+    _values: Vec<RawVariant>,
+    _attrs: Vec<ll::TagLib_Complex_Property_Attribute>,
+    ptrs: Vec<*const ll::TagLib_Complex_Property_Attribute>,
+}
+
+impl RawComplexProperty {
+    fn new(value: &ComplexProperty) -> Result<RawComplexProperty, FileError> {
+        let mut keys = Vec::with_capacity(value.len());
+        let mut values = Vec::with_capacity(value.len());
+        for (k, v) in value {
+            keys.push(key_to_c_string(k)?);
+            values.push(v.to_raw().ok_or_else(|| FileError::InvalidValue(k.to_owned()))?);
+        }
+
+        let attrs = keys.iter().zip(values.iter())
+            .map(|(k, v)| ll::TagLib_Complex_Property_Attribute {
+                key: k.as_ptr() as *mut c_char,
+                value: *v.as_raw(),
+            })
+            .collect::<Vec<_>>();
+        let mut ptrs = attrs.iter()
+            .map(|a| a as *const ll::TagLib_Complex_Property_Attribute)
+            .collect::<Vec<_>>();
+        ptrs.push(ptr::null());
+
+        Ok(RawComplexProperty { _keys: keys, _values: values, _attrs: attrs, ptrs })
+    }
+
+    fn as_ptr(&self) -> *const *const ll::TagLib_Complex_Property_Attribute {
+        self.ptrs.as_ptr()
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut *const ll::TagLib_Complex_Property_Attribute {
+        self.ptrs.as_mut_ptr()
+    }
+}
+
+/// A NULL terminated array of properties, as expected by
+/// `taglib_shim_set_property_map()`, together with the strings they point to.
+struct RawPropertyMap {
+    _strings: Vec<CString>,
+    _props: Vec<Vec<*const c_char>>,
+    ptrs: Vec<*const *const c_char>,
+}
+
+impl RawPropertyMap {
+    fn new(map: &PropertyMap) -> Result<RawPropertyMap, FileError> {
+        let mut strings = Vec::new();
+        let mut props = Vec::with_capacity(map.len());
+        for (key, values) in map {
+            let mut prop = Vec::with_capacity(values.len() + 2);
+            let cs = key_to_c_string(key)?;
+            prop.push(cs.as_ptr());
+            strings.push(cs);
+            for value in values {
+                let cs = value_to_c_string(value)?;
+                prop.push(cs.as_ptr());
+                strings.push(cs);
+            }
+            prop.push(ptr::null());
+            props.push(prop);
+        }
+
+        let mut ptrs = props.iter().map(|p| p.as_ptr()).collect::<Vec<_>>();
+        ptrs.push(ptr::null());
+
+        Ok(RawPropertyMap { _strings: strings, _props: props, ptrs })
+    }
+
+    fn as_ptr(&self) -> *const *const *const c_char {
+        self.ptrs.as_ptr()
+    }
+}
+
 fn decimal_to_padding_string(decimal: u32, padding: usize) -> String {
     format!("{:0width$}", decimal, width = padding)
 }
@@ -978,13 +1981,69 @@ fn decimal_to_padding_string(decimal: u32, padding: usize) -> String {
 /// kid3-cli -c 'set artist "Artist"' test.mp3
 #[cfg(test)]
 mod test {
-    use std::fs;
+    use std::error::Error;
+    use std::{env, fs, io, process, thread};
     use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
     use super::*;
 
     const TEST_MP3: &'static str = "fixtures/test.mp3";
     const TEST_FLAC: &'static str = "fixtures/test.flac";
+    const TEST_PIC: &str = "fixtures/pic.jpg";
+
+    // a path for a scratch file, in a directory of the test process
+    fn temp_file<P: AsRef<Path>>(name: P) -> PathBuf {
+        let dir = env::temp_dir().join(format!("taglib-rust-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    #[test]
+    fn test_file_type_names() {
+        assert_eq!(FileType::all().len(), 20);
+        for (i, t) in FileType::all().iter().enumerate() {
+            assert_eq!(*t as usize, i);
+            assert_eq!(t.to_string(), t.name());
+            assert_eq!(t.name().to_lowercase().parse::<FileType>(), Ok(*t));
+        }
+        assert_eq!("Ogg".parse::<FileType>(), Err(ParseFileTypeError("Ogg".to_owned())));
+    }
+
+    #[test]
+    fn test_file_type_suffix() {
+        let mut seen = HashSet::new();
+        for t in FileType::all() {
+            for suffix in t.suffix() {
+                assert!(suffix.starts_with('.'), "{}", suffix);
+                assert!(seen.insert(suffix), "{}", suffix);
+                assert_eq!(FileType::from_extension(suffix), Some(*t));
+            }
+        }
+        assert_eq!(seen.len(), FileType::all_suffix().len());
+        assert_eq!(FileType::DFF.suffix(), &vec![".dff", ".dsdiff"]);
+        assert_eq!(FileType::from_extension("M4B"), Some(FileType::MP4));
+        assert_eq!(FileType::from_extension(".Flac"), Some(FileType::FLAC));
+        assert_eq!(FileType::from_extension("txt"), None);
+    }
+
+    #[test]
+    fn test_register_extension() {
+        assert_eq!(FileType::from_extension(".wave"), None);
+        FileType::register_extension(".WAVE", FileType::WAV);
+        assert_eq!(FileType::from_extension("wave"), Some(FileType::WAV));
+
+        let temp_fn = temp_file("registered.wave");
+        fs::copy(TEST_FLAC, &temp_fn).unwrap();
+        assert!(File::new(&temp_fn).is_err());
+        FileType::register_extension("wave", FileType::FLAC);
+        assert!(File::new(&temp_fn).is_ok());
+        fs::remove_file(&temp_fn).unwrap();
+
+        assert_eq!(FileType::unregister_extension("Wave"), Some(FileType::FLAC));
+        assert_eq!(FileType::unregister_extension("wave"), None);
+    }
 
     #[test]
     fn test_get_number_pair() {
@@ -1027,6 +2086,19 @@ mod test {
         assert_eq!(tag.artist().unwrap(), "Artist");
     }
 
+    #[test]
+    fn test_get_tag_after_other_file_dropped() {
+        let file = File::new(TEST_MP3).unwrap();
+        let other = File::new(TEST_MP3).unwrap();
+        assert_eq!(other.tag().unwrap().artist().unwrap(), "Artist");
+        drop(other);
+
+        let tag = file.tag().unwrap();
+        for _ in 0..1000 {
+            assert_eq!(tag.artist().unwrap(), "Artist");
+        }
+    }
+
     #[test]
     fn test_get_pathbuf() {
         let file = File::new(PathBuf::from(TEST_MP3)).unwrap();
@@ -1034,6 +2106,49 @@ mod test {
         assert_eq!(tag.artist().unwrap(), "Artist");
     }
 
+    #[test]
+    #[cfg(unix)]
+    fn test_get_non_utf8_path() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let temp_fn = temp_file(OsStr::from_bytes(b"\xe9t\xe9.mp3"));
+        assert!(temp_fn.to_str().is_none());
+        fs::copy(TEST_MP3, &temp_fn).unwrap();
+
+        let file = File::new(&temp_fn).unwrap();
+        assert_eq!(file.tag().unwrap().artist().unwrap(), "Artist");
+        let file = File::new_type(&temp_fn, FileType::MPEG).unwrap();
+        assert_eq!(file.tag().unwrap().artist().unwrap(), "Artist");
+
+        fs::remove_file(&temp_fn).unwrap();
+    }
+
+    #[test]
+    fn test_open_errors() {
+        let err = File::new("fixtures/missing.mp3").err().unwrap();
+        assert!(matches!(err, FileError::NotFound { .. }));
+        assert_eq!(err.path(), Some(Path::new("fixtures/missing.mp3")));
+        assert!(err.source().is_some());
+        assert_eq!(err.to_string(), "file not found: fixtures/missing.mp3");
+
+        let temp_fn = temp_file("unsupported.txt");
+        fs::write(&temp_fn, "not an audio file").unwrap();
+        let err = File::new(&temp_fn).err().unwrap();
+        assert!(matches!(err, FileError::UnsupportedFormat { .. }));
+        assert_eq!(err.path(), Some(temp_fn.as_path()));
+        fs::remove_file(&temp_fn).unwrap();
+
+        let err = File::new_type(TEST_MP3, FileType::FLAC).err().unwrap();
+        assert!(matches!(err, FileError::CorruptFile { .. }));
+
+        let err = File::new("fixtures/a\0b.mp3").err().unwrap();
+        assert!(matches!(err, FileError::InvalidFileName { .. }));
+
+        let boxed: Box<dyn Error> = Box::new(err);
+        assert!(boxed.to_string().starts_with("invalid file name"));
+    }
+
     #[test]
     fn test_get_no_tag() {
         let file = File::new(TEST_MP3).unwrap();
@@ -1049,27 +2164,670 @@ mod test {
     }
 
     #[test]
+    #[allow(deprecated)]
     fn test_get_audioproperties() {
         let file = File::new(TEST_MP3).unwrap();
         let ap = file.audioproperties().unwrap();
         assert_eq!(ap.length(), 0);
+        assert!(ap.duration() < Duration::from_secs(1));
+        assert_eq!(u64::from(ap.length()), ap.duration().as_secs());
     }
 
     #[test]
     fn test_set_tag() {
-        let temp_fn = "fixtures/temp.mp3";
-        fs::copy(TEST_MP3, temp_fn).unwrap();
-        let file = File::new(temp_fn).unwrap();
-        let mut tag = file.tag().unwrap();
-        tag.set_artist("Not Artist");
+        let temp_fn = temp_file(".mp3");
+        fs::copy(TEST_MP3, &temp_fn).unwrap();
+        let mut file = File::new(&temp_fn).unwrap();
+        let mut tag = file.tag_mut().unwrap();
+        tag.set_artist("Not Artist").unwrap();
         assert_eq!(tag.artist().unwrap(), "Not Artist");
 
-        file.save();
+        file.save().unwrap();
 
-        let file = File::new(temp_fn).unwrap();
+        let file = File::new(&temp_fn).unwrap();
+        let tag = file.tag().unwrap();
+        assert_eq!(tag.artist().unwrap(), "Not Artist");
+
+        fs::remove_file(&temp_fn).unwrap();
+    }
+
+    #[test]
+    fn test_set_invalid_property() {
+        let data = fs::read(TEST_MP3).unwrap();
+        let mut file = File::from_bytes(&data, None).unwrap();
+
+        let err = file.tag_mut().unwrap().set_artist("Not\0Artist").err().unwrap();
+        assert!(matches!(err, FileError::InvalidValue(ref v) if v == "Not\0Artist"));
+        assert!(matches!(file.set_property("ARTIST", "Not\0Artist"),
+                         Err(FileError::InvalidValue(_))));
+        assert!(matches!(file.set_property("ART\0IST", "Artist"),
+                         Err(FileError::InvalidKey(ref k)) if k == "ART\0IST"));
+        assert!(matches!(file.get_property("ART\0IST"), Err(FileError::InvalidKey(_))));
+        assert!(matches!(file.remove_property("ART\0IST"), Err(FileError::InvalidKey(_))));
+
+        let mut geob = ComplexProperty::new();
+        geob.insert("data".to_owned(), Variant::String("a\0b".to_owned()));
+        assert!(matches!(file.set_complex_property("GENERALOBJECT", &geob),
+                         Err(FileError::InvalidValue(ref k)) if k == "data"));
+
+        assert_eq!(file.tag().unwrap().artist().unwrap(), "Artist");
+    }
+
+    #[test]
+    fn test_file_type_from_raw() {
+        for filetype in FileType::all() {
+            assert_eq!(FileType::from_raw(*filetype as ll::TagLib_FileType), Some(*filetype));
+        }
+        assert_eq!(FileType::from_raw(FileType::all().len() as ll::TagLib_FileType), None);
+    }
+
+    #[test]
+    fn test_write_error() {
+        let path = Path::new("full.mp3");
+        assert!(matches!(write_error(path, libc::ENOSPC), Some(SaveError::DiskFull { .. })));
+        assert!(matches!(write_error(path, libc::EROFS), Some(SaveError::ReadOnlyFile { .. })));
+        assert!(matches!(write_error(path, libc::EACCES), Some(SaveError::ReadOnlyFile { .. })));
+        assert!(write_error(path, libc::EINTR).is_none());
+        assert!(write_error(path, 0).is_none());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_save_read_only_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_fn = temp_file("read_only.mp3");
+        fs::copy(TEST_MP3, &temp_fn).unwrap();
+        fs::set_permissions(&temp_fn, fs::Permissions::from_mode(0o444)).unwrap();
+
+        // permissions are not enforced for root
+        if fs::OpenOptions::new().write(true).open(&temp_fn).is_err() {
+            let mut file = File::new(&temp_fn).unwrap();
+            assert!(file.is_read_only());
+            file.tag_mut().unwrap().set_artist("Not Artist").unwrap();
+            let err = file.save().err().unwrap();
+            assert!(matches!(err, SaveError::ReadOnlyFile { .. }));
+            assert_eq!(err.to_string(),
+                       format!("could not save {}: file is read-only", temp_fn.display()));
+        }
+
+        fs::remove_file(&temp_fn).unwrap();
+    }
+
+    #[test]
+    fn test_save_atomic() {
+        let temp_fn = temp_file("atomic.mp3");
+        fs::copy(TEST_MP3, &temp_fn).unwrap();
+        let mut file = File::new(&temp_fn).unwrap();
+        file.tag_mut().unwrap().set_artist("Not Artist").unwrap();
+        file.save_atomic().unwrap();
+        assert_eq!(file.tag().unwrap().artist().unwrap(), "Not Artist");
+
+        // all pending changes are saved, not only those in a snapshot
+        file.tag_mut().unwrap().set_album("Album").unwrap();
+        let picture = Picture {
+            mime_type: "image/jpeg".to_owned(),
+            description: String::new(),
+            picture_type: PictureType::FrontCover,
+            data: fs::read(TEST_PIC).unwrap(),
+        };
+        file.add_picture(&picture).unwrap();
+        file.save_atomic().unwrap();
+
+        // the file is still the one at the path
+        file.tag_mut().unwrap().set_comment("Comment").unwrap();
+        file.save().unwrap();
+        drop(file);
+
+        let mut file = File::new(&temp_fn).unwrap();
         let tag = file.tag().unwrap();
         assert_eq!(tag.artist().unwrap(), "Not Artist");
+        assert_eq!(tag.album().unwrap(), "Album");
+        assert_eq!(tag.comment().unwrap(), "Comment");
+        assert_eq!(file.pictures().unwrap(), vec![picture]);
+
+        // removals are carried over to the copy as well
+        file.remove_pictures().unwrap();
+        file.tag_mut().unwrap().set_album("").unwrap();
+        file.save_atomic().unwrap();
+        drop(file);
+
+        let file = File::new(&temp_fn).unwrap();
+        assert!(file.pictures().unwrap().is_empty());
+        assert_eq!(file.tag().unwrap().album(), None);
+        assert_eq!(file.tag().unwrap().comment().unwrap(), "Comment");
+        let leftover = fs::read_dir(temp_fn.parent().unwrap()).unwrap()
+            .any(|e| e.unwrap().file_name().to_string_lossy().ends_with(".atomic.mp3"));
+        assert!(!leftover);
+
+        fs::remove_file(&temp_fn).unwrap();
+
+        let mut file = File::from_bytes(&fs::read(TEST_MP3).unwrap(), None).unwrap();
+        let err = file.save_atomic().err().unwrap();
+        assert!(matches!(err, SaveError::NotOnDisk));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_save_atomic_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_fn = temp_file("atomic_permissions.mp3");
+        fs::copy(TEST_MP3, &temp_fn).unwrap();
+        fs::set_permissions(&temp_fn, fs::Permissions::from_mode(0o640)).unwrap();
+        let mut file = File::new(&temp_fn).unwrap();
+        file.tag_mut().unwrap().set_artist("Not Artist").unwrap();
+        file.save_atomic().unwrap();
+
+        let mode = fs::metadata(&temp_fn).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+
+        fs::remove_file(&temp_fn).unwrap();
+    }
+
+    #[test]
+    fn test_properties() {
+        let data = fs::read(TEST_MP3).unwrap();
+        let mut file = File::from_bytes(&data, None).unwrap();
+        let mut props = file.properties().unwrap();
+        assert_eq!(props.get("ARTIST"), Some(&vec!["Artist".to_owned()]));
+
+        props.remove("ARTIST");
+        props.insert("TITLE".to_owned(), vec!["Title".to_owned()]);
+        props.insert("GENRE".to_owned(), vec!["Rock".to_owned(), "Pop".to_owned()]);
+        assert!(file.set_properties(&props).unwrap().is_empty());
+        file.save().unwrap();
+
+        let file = File::from_bytes(&file.bytes().unwrap(), None).unwrap();
+        assert_eq!(file.properties().unwrap(), props);
+        assert_eq!(file.tag().unwrap().artist(), None);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let file = File::new(TEST_MP3).unwrap();
+        let metadata = file.snapshot();
+        drop(file);
+
+        let tag = metadata.tag.as_ref().unwrap();
+        assert_eq!(tag.artist.as_deref(), Some("Artist"));
+        assert_eq!(tag.album, None);
+        let properties = metadata.properties.as_ref().unwrap();
+        assert_eq!(properties.get("ARTIST"), Some(&vec!["Artist".to_owned()]));
+        assert_eq!(metadata.pictures, Some(Vec::new()));
+        assert!(metadata.audio.as_ref().unwrap().length < Duration::from_secs(1));
+
+        let copy = thread::spawn({
+            let metadata = metadata.clone();
+            move || metadata
+        }).join().unwrap();
+        assert_eq!(copy, metadata);
+
+        let file = File::new(TEST_MP3).unwrap();
+        assert_eq!(file.snapshot(), metadata);
+        let mut file = File::from_bytes(&fs::read(TEST_MP3).unwrap(), None).unwrap();
+        file.tag_mut().unwrap().set_album("Album").unwrap();
+        assert_ne!(file.snapshot(), metadata);
+    }
+
+    #[test]
+    fn test_snapshot_pictures() {
+        let picture = Picture {
+            mime_type: "image/jpeg".to_owned(),
+            description: "Cover".to_owned(),
+            picture_type: PictureType::FrontCover,
+            data: fs::read(TEST_PIC).unwrap(),
+        };
+        let mut file = File::from_bytes(&fs::read(TEST_MP3).unwrap(), None).unwrap();
+        file.add_picture(&picture).unwrap();
+
+        let described = file.snapshot().pictures.unwrap();
+        assert_eq!(described.len(), 1);
+        assert_eq!(described[0].description, "Cover");
+        assert_eq!(described[0].size, picture.data.len());
+        assert_eq!(described[0].data, None);
+
+        let full = file.snapshot_with_picture_data().pictures.unwrap();
+        assert_eq!(full, vec![PictureInfo::from(picture.clone())]);
+        assert_eq!(full[0].to_picture(), Some(picture));
+        assert!(file.snapshot_with_picture_data().diff(&file.snapshot()).is_empty());
+    }
+
+    #[test]
+    fn test_diff_apply() {
+        let mut file = File::from_bytes(&fs::read(TEST_MP3).unwrap(), None).unwrap();
+        let mut target = file.snapshot();
+        let tag = target.tag.as_mut().unwrap();
+        tag.artist = None;
+        tag.album = Some("Album".to_owned());
+        tag.track = Some(3);
+        let properties = target.properties.as_mut().unwrap();
+        properties.remove("ARTIST");
+        properties.insert("ALBUM".to_owned(), vec!["Album".to_owned()]);
+        properties.insert("TRACKNUMBER".to_owned(), vec!["3/12".to_owned()]);
+
+        let diff = file.diff(&target);
+        assert!(!diff.is_empty());
+        assert!(file.apply(&diff).unwrap().is_empty());
+        file.save().unwrap();
+
+        let mut file = File::from_bytes(&file.bytes().unwrap(), None).unwrap();
+        assert_eq!(file.get_property("TRACKNUMBER").unwrap(), vec!["3/12".to_owned()]);
+        assert!(file.diff(&target).is_empty());
+
+        // there is no picture to take the data from
+        target.pictures.as_mut().unwrap().push(PictureInfo {
+            mime_type: "image/jpeg".to_owned(),
+            description: String::new(),
+            picture_type: PictureType::FrontCover,
+            size: 1024,
+            data: None,
+        });
+        let diff = file.diff(&target);
+        assert!(matches!(file.apply(&diff), Err(FileError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_unsupported_properties() {
+        let data = fs::read(TEST_MP3).unwrap();
+        let mut file = File::from_bytes(&data, None).unwrap();
+        assert!(file.unsupported_properties().unwrap().is_empty());
+
+        let picture = Picture {
+            mime_type: "image/jpeg".to_owned(),
+            description: String::new(),
+            picture_type: PictureType::FrontCover,
+            data: fs::read(TEST_PIC).unwrap(),
+        };
+        file.add_picture(&picture).unwrap();
+        file.save().unwrap();
+
+        let mut file = File::from_bytes(&file.bytes().unwrap(), None).unwrap();
+        let unsupported = file.unsupported_properties().unwrap();
+        assert_eq!(unsupported, vec!["APIC".to_owned()]);
+
+        file.remove_unsupported_properties(&unsupported).unwrap();
+        file.save().unwrap();
+
+        let file = File::from_bytes(&file.bytes().unwrap(), None).unwrap();
+        assert!(file.unsupported_properties().unwrap().is_empty());
+        assert!(file.pictures().unwrap().is_empty());
+        assert_eq!(file.tag().unwrap().artist().unwrap(), "Artist");
+    }
+
+    #[test]
+    fn test_complex_property() {
+        let temp_fn = temp_file("complex.mp3");
+        fs::copy(TEST_MP3, &temp_fn).unwrap();
+        let mut file = File::new(&temp_fn).unwrap();
+        assert!(file.complex_property("GENERALOBJECT").unwrap().is_empty());
+
+        let mut geob = ComplexProperty::new();
+        geob.insert("data".to_owned(), Variant::Bytes(vec![0, 1, 2, 0, 255]));
+        geob.insert("mimeType".to_owned(), Variant::String("application/octet-stream".to_owned()));
+        geob.insert("description".to_owned(), Variant::String("Blob".to_owned()));
+        geob.insert("fileName".to_owned(), Variant::String("blob.bin".to_owned()));
+        file.set_complex_property("GENERALOBJECT", &geob).unwrap();
+        file.save().unwrap();
+
+        let mut file = File::new(&temp_fn).unwrap();
+        assert!(file.complex_property_keys().unwrap().contains(&"GENERALOBJECT".to_owned()));
+        assert_eq!(file.complex_property("GENERALOBJECT").unwrap(), vec![geob]);
+
+        file.remove_complex_property("GENERALOBJECT").unwrap();
+        assert!(file.complex_property("GENERALOBJECT").unwrap().is_empty());
+
+        fs::remove_file(&temp_fn).unwrap();
+    }
+
+    fn test_pictures_round_trip(source: &str, temp_fn: &Path) {
+        fs::copy(source, temp_fn).unwrap();
+        let picture = Picture {
+            mime_type: "image/jpeg".to_owned(),
+            description: "Cover".to_owned(),
+            picture_type: PictureType::FrontCover,
+            data: fs::read(TEST_PIC).unwrap(),
+        };
+        let mut file = File::new(temp_fn).unwrap();
+        assert!(file.pictures().unwrap().is_empty());
+        file.set_pictures(std::slice::from_ref(&picture)).unwrap();
+        file.save().unwrap();
+
+        let mut file = File::new(temp_fn).unwrap();
+        assert_eq!(file.pictures().unwrap(), vec![picture.clone()]);
+
+        let back = Picture {
+            description: "Back".to_owned(),
+            picture_type: PictureType::BackCover,
+            ..picture.clone()
+        };
+        file.add_picture(&back).unwrap();
+        file.save().unwrap();
+
+        let mut file = File::new(temp_fn).unwrap();
+        assert_eq!(file.pictures().unwrap(), vec![picture.clone(), back.clone()]);
+
+        // an invalid picture leaves all of them unchanged
+        let invalid = Picture { mime_type: "image/\0".to_owned(), ..picture.clone() };
+        assert!(file.set_pictures(&[picture.clone(), invalid]).is_err());
+        assert_eq!(file.pictures().unwrap(), vec![picture, back]);
+
+        file.remove_pictures().unwrap();
+        file.save().unwrap();
+
+        let file = File::new(temp_fn).unwrap();
+        assert!(file.pictures().unwrap().is_empty());
 
         fs::remove_file(temp_fn).unwrap();
     }
+
+    #[test]
+    fn test_pictures_mp3() {
+        test_pictures_round_trip(TEST_MP3, &temp_file("pictures.mp3"));
+    }
+
+    #[test]
+    fn test_pictures_flac() {
+        test_pictures_round_trip(TEST_FLAC, &temp_file("pictures.flac"));
+    }
+
+    #[test]
+    fn test_open_detected() {
+        let temp_fn = temp_file("misnamed.mp3");
+        fs::copy(TEST_FLAC, &temp_fn).unwrap();
+        let mut file = File::open_detected(&temp_fn).unwrap();
+        assert!(file.audioproperties().unwrap().samplerate() > 0);
+        file.tag_mut().unwrap().set_artist("Not Artist").unwrap();
+        file.save().unwrap();
+        drop(file);
+
+        assert_eq!(FileType::detect(&fs::read(&temp_fn).unwrap()), Some(FileType::FLAC));
+        let file = File::new_type(&temp_fn, FileType::FLAC).unwrap();
+        assert_eq!(file.tag().unwrap().artist().unwrap(), "Not Artist");
+        fs::remove_file(&temp_fn).unwrap();
+
+        assert!(File::open_detected(TEST_MP3).is_ok());
+    }
+
+    #[test]
+    fn test_file_type() {
+        assert_eq!(File::new(TEST_MP3).unwrap().file_type(), Some(FileType::MPEG));
+        assert_eq!(File::new(TEST_FLAC).unwrap().file_type(), Some(FileType::FLAC));
+
+        let data = fs::read(TEST_FLAC).unwrap();
+        assert_eq!(File::from_bytes(&data, None).unwrap().file_type(), Some(FileType::FLAC));
+
+        let temp_fn = temp_file("file_type.mp3");
+        fs::copy(TEST_FLAC, &temp_fn).unwrap();
+        assert_eq!(File::open_detected(&temp_fn).unwrap().file_type(), Some(FileType::FLAC));
+        fs::remove_file(&temp_fn).unwrap();
+    }
+
+    #[test]
+    fn test_file_type_resolver() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        let id = File::add_file_type_resolver(|path: &Path| {
+            if !path.ends_with("resolved.cache") {
+                return None;
+            }
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            Some(FileType::FLAC)
+        });
+
+        let temp_fn = temp_file("resolved.cache");
+        fs::copy(TEST_FLAC, &temp_fn).unwrap();
+        let file = File::new(&temp_fn).unwrap();
+        assert_eq!(file.file_type(), Some(FileType::FLAC));
+        assert!(file.tag().is_ok());
+        drop(file);
+
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+
+        assert!(File::remove_file_type_resolver(id));
+        assert!(!File::remove_file_type_resolver(id));
+        let _ = File::new(&temp_fn);
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+        fs::remove_file(&temp_fn).unwrap();
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let data = fs::read(TEST_MP3).unwrap();
+        let mut file = File::from_bytes(&data, None).unwrap();
+        assert_eq!(file.tag().unwrap().artist().unwrap(), "Artist");
+        assert_eq!(file.bytes().unwrap(), data);
+
+        file.tag_mut().unwrap().set_artist("Not Artist").unwrap();
+        file.save().unwrap();
+        let saved = file.bytes().unwrap();
+        assert_ne!(saved, data);
+
+        let file = File::from_bytes(&saved, Some(FileType::MPEG)).unwrap();
+        assert_eq!(file.tag().unwrap().artist().unwrap(), "Not Artist");
+
+        assert!(File::new(TEST_MP3).unwrap().bytes().is_none());
+        assert!(File::from_bytes(b"not an audio file", None).is_err());
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let data = fs::read(TEST_MP3).unwrap();
+        let mut file = File::from_reader(io::Cursor::new(data), None).unwrap();
+        assert_eq!(file.tag().unwrap().artist().unwrap(), "Artist");
+        assert!(file.bytes().is_none());
+
+        file.tag_mut().unwrap().set_artist("Not Artist").unwrap();
+        assert!(matches!(file.save(), Err(SaveError::OpenedReadOnly { path: None })));
+    }
+
+    #[test]
+    fn test_from_stream() {
+        let temp_fn = temp_file("stream.flac");
+        fs::copy(TEST_FLAC, &temp_fn).unwrap();
+        let stream = fs::OpenOptions::new().read(true).write(true).open(&temp_fn).unwrap();
+        let mut file = File::from_stream(stream, Some(FileType::FLAC)).unwrap();
+        file.tag_mut().unwrap().set_artist("A rather long artist name to grow the tag").unwrap();
+        file.save().unwrap();
+        file.tag_mut().unwrap().set_artist("Short").unwrap();
+        file.save().unwrap();
+        drop(file);
+
+        let file = File::new(&temp_fn).unwrap();
+        assert_eq!(file.tag().unwrap().artist().unwrap(), "Short");
+
+        fs::remove_file(&temp_fn).unwrap();
+    }
+
+    #[test]
+    fn test_from_stream_moves_large_blocks() {
+        // The file and the picture are both much larger than the buffer the
+        // stream uses to move data, so inserting and removing the picture
+        // has to shift the audio in several blocks.
+        let original = fs::read(TEST_FLAC).unwrap();
+        let audio = &original[original.len() - 1_000_000..];
+        let picture = Picture {
+            mime_type: "image/jpeg".to_owned(),
+            description: "Large".to_owned(),
+            picture_type: PictureType::FrontCover,
+            data: (0..1_500_000u32).map(|i| (i % 251) as u8).collect(),
+        };
+
+        let temp_fn = temp_file("large.flac");
+        fs::copy(TEST_FLAC, &temp_fn).unwrap();
+        let stream = fs::OpenOptions::new().read(true).write(true).open(&temp_fn).unwrap();
+        let mut file = File::from_stream(stream, Some(FileType::FLAC)).unwrap();
+        file.add_picture(&picture).unwrap();
+        file.save().unwrap();
+        drop(file);
+
+        let grown = fs::read(&temp_fn).unwrap();
+        assert!(grown.len() > original.len() + picture.data.len());
+        assert!(grown.ends_with(audio));
+        let file = File::new(&temp_fn).unwrap();
+        assert_eq!(file.pictures().unwrap(), vec![picture]);
+        drop(file);
+
+        let stream = fs::OpenOptions::new().read(true).write(true).open(&temp_fn).unwrap();
+        let mut file = File::from_stream(stream, Some(FileType::FLAC)).unwrap();
+        file.remove_pictures().unwrap();
+        file.save().unwrap();
+        drop(file);
+
+        let shrunk = fs::read(&temp_fn).unwrap();
+        assert!(shrunk.len() < grown.len());
+        assert!(shrunk.ends_with(audio));
+        let file = File::new(&temp_fn).unwrap();
+        assert!(file.pictures().unwrap().is_empty());
+
+        fs::remove_file(&temp_fn).unwrap();
+    }
+
+    #[test]
+    fn test_from_stream_read_error() {
+        // Once `failing` is set, every read stops with an error after a byte
+        struct FlakyDisk {
+            data: io::Cursor<Vec<u8>>,
+            failing: Arc<AtomicBool>,
+            read_byte: bool,
+        }
+
+        impl io::Read for FlakyDisk {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if !self.failing.load(Ordering::SeqCst) || buf.is_empty() {
+                    return self.data.read(buf);
+                }
+                self.read_byte = !self.read_byte;
+                if self.read_byte {
+                    self.data.read(&mut buf[..1])
+                } else {
+                    Err(io::Error::other("bad sector"))
+                }
+            }
+        }
+
+        impl io::Seek for FlakyDisk {
+            fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+                self.data.seek(pos)
+            }
+        }
+
+        impl io::Write for FlakyDisk {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.data.write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        impl Truncate for FlakyDisk {
+            fn truncate(&mut self, len: u64) -> io::Result<()> {
+                self.data.truncate(len)
+            }
+        }
+
+        let failing = Arc::new(AtomicBool::new(false));
+        let stream = FlakyDisk {
+            data: io::Cursor::new(fs::read(TEST_FLAC).unwrap()),
+            failing: failing.clone(),
+            read_byte: false,
+        };
+        let mut file = File::from_stream(stream, Some(FileType::FLAC)).unwrap();
+        file.tag_mut().unwrap().set_artist("A rather long artist name to grow the tag").unwrap();
+        failing.store(true, Ordering::SeqCst);
+        match file.save() {
+            Err(SaveError::Io { source, .. }) => assert_eq!(source.to_string(), "bad sector"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_from_stream_write_error() {
+        struct ReadOnlyDisk(io::Cursor<Vec<u8>>);
+
+        impl io::Read for ReadOnlyDisk {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.0.read(buf)
+            }
+        }
+
+        impl io::Seek for ReadOnlyDisk {
+            fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+                self.0.seek(pos)
+            }
+        }
+
+        impl io::Write for ReadOnlyDisk {
+            fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::PermissionDenied.into())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        impl Truncate for ReadOnlyDisk {
+            fn truncate(&mut self, _len: u64) -> io::Result<()> {
+                panic!("truncate() should not be reached");
+            }
+        }
+
+        let stream = ReadOnlyDisk(io::Cursor::new(fs::read(TEST_FLAC).unwrap()));
+        let mut file = File::from_stream(stream, Some(FileType::FLAC)).unwrap();
+        file.tag_mut().unwrap().set_artist("A rather long artist name to grow the tag").unwrap();
+        match file.save() {
+            Err(SaveError::Io { source, .. }) => {
+                assert_eq!(source.kind(), io::ErrorKind::PermissionDenied);
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_edit_in_other_thread() {
+        let temp_fn = temp_file("thread.mp3");
+        fs::copy(TEST_MP3, &temp_fn).unwrap();
+        let file = File::new(&temp_fn).unwrap();
+
+        let file = thread::spawn(move || {
+            let mut file = file;
+            file.tag_mut().unwrap().set_artist("Not Artist").unwrap();
+            file.save().unwrap();
+            file
+        }).join().unwrap();
+        assert_eq!(file.tag().unwrap().artist().unwrap(), "Not Artist");
+        drop(file);
+
+        let file = File::new(&temp_fn).unwrap();
+        assert_eq!(file.tag().unwrap().artist().unwrap(), "Not Artist");
+
+        fs::remove_file(&temp_fn).unwrap();
+    }
+
+    #[test]
+    fn test_edit_in_parallel() {
+        let data = fs::read(TEST_MP3).unwrap();
+        let threads: Vec<_> = (0..8).map(|i| {
+            let data = data.clone();
+            thread::spawn(move || {
+                let artist = format!("Artist {}", i);
+                let mut file = File::from_bytes(&data, None).unwrap();
+                for _ in 0..100 {
+                    file.tag_mut().unwrap().set_artist(&artist).unwrap();
+                    assert_eq!(file.tag().unwrap().artist().unwrap(), artist);
+                }
+                file.save().unwrap();
+
+                let file = File::from_bytes(&file.bytes().unwrap(), None).unwrap();
+                assert_eq!(file.tag().unwrap().artist().unwrap(), artist);
+            })
+        }).collect();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+    }
 }
@@ -0,0 +1,290 @@
+// Copyright 2015  Emmanuele Bassi. All rights reserved.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+
+use std::time::Duration;
+
+use super::{AudioProperties, Picture, PictureType, PropertyMap, Tag};
+
+/// An owned snapshot of the meta-data and audio properties of a file.
+///
+/// Instances are usually created by `taglib::File::snapshot()`, and stay
+/// valid after the file is dropped.
+///
+/// Parts that could not be read, e.g. because a value is not valid UTF-8,
+/// are `None`; the corresponding methods of `taglib::File` return the
+/// reason.  `Metadata::diff()` leaves such parts unchanged.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Metadata {
+    /// The basic tag fields, if the file has a tag
+    pub tag: Option<TagInfo>,
+    /// All properties of the file
+    pub properties: Option<PropertyMap>,
+    /// The pictures embedded in the file
+    pub pictures: Option<Vec<PictureInfo>>,
+    /// The audio properties, if available
+    pub audio: Option<AudioInfo>,
+}
+
+/// The description of a picture embedded in a file, as found in a
+/// `Metadata` snapshot.
+///
+/// The image data is only kept by
+/// `taglib::File::snapshot_with_picture_data()`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PictureInfo {
+    /// The MIME type of the image, e.g. "image/jpeg"
+    pub mime_type: String,
+    /// A description of the picture
+    pub description: String,
+    /// The type of the picture
+    pub picture_type: PictureType,
+    /// The size of the image data, in bytes
+    pub size: usize,
+    /// The image data, if it was kept
+    #[cfg_attr(feature = "serde", serde(with = "super::picture::base64_data::option",
+                                        default,
+                                        skip_serializing_if = "Option::is_none"))]
+    pub data: Option<Vec<u8>>,
+}
+
+impl PictureInfo {
+    /// Returns the description without the image data.
+    pub fn without_data(self) -> PictureInfo {
+        PictureInfo { data: None, ..self }
+    }
+
+    /// Returns the picture, if the image data was kept.
+    pub fn to_picture(&self) -> Option<Picture> {
+        Some(Picture {
+            mime_type: self.mime_type.clone(),
+            description: self.description.clone(),
+            picture_type: self.picture_type,
+            data: self.data.clone()?,
+        })
+    }
+
+    // whether both describe the same picture, comparing the image data only
+    // if both kept it
+    pub(crate) fn same_as(&self, other: &PictureInfo) -> bool {
+        self.mime_type == other.mime_type
+            && self.description == other.description
+            && self.picture_type == other.picture_type
+            && self.size == other.size
+            && (self.data.is_none() || other.data.is_none() || self.data == other.data)
+    }
+}
+
+impl From<Picture> for PictureInfo {
+    fn from(picture: Picture) -> PictureInfo {
+        PictureInfo {
+            mime_type: picture.mime_type,
+            description: picture.description,
+            picture_type: picture.picture_type,
+            size: picture.data.len(),
+            data: Some(picture.data),
+        }
+    }
+}
+
+/// An owned copy of the fields of a `taglib::Tag`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TagInfo {
+    /// The track name
+    pub title: Option<String>,
+    /// The artist name
+    pub artist: Option<String>,
+    /// The album name
+    pub album: Option<String>,
+    /// The track comment
+    pub comment: Option<String>,
+    /// The genre name
+    pub genre: Option<String>,
+    /// The year
+    pub year: Option<u32>,
+    /// The track number
+    pub track: Option<u32>,
+}
+
+#[cfg(feature = "serde")]
+impl Metadata {
+    /// Returns a wrapper that serializes the snapshot without the image
+    /// data of the pictures, e.g. to serialize only their descriptions.
+    ///
+    /// Pictures without data keep their image data in `Metadata::diff()`
+    /// and `taglib::File::apply()`.
+    pub fn without_picture_data(&self) -> WithoutPictureData<'_> {
+        WithoutPictureData(self)
+    }
+}
+
+/// Serializes a `Metadata` snapshot without the image data of its pictures,
+/// see `Metadata::without_picture_data()`.
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, Copy)]
+pub struct WithoutPictureData<'a>(pub &'a Metadata);
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for WithoutPictureData<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // the same shape as `Metadata` and `PictureInfo`, minus the data
+        #[derive(serde::Serialize)]
+        #[serde(rename = "Metadata")]
+        struct MetadataRef<'a> {
+            tag: &'a Option<TagInfo>,
+            properties: &'a Option<PropertyMap>,
+            pictures: Option<Vec<PictureRef<'a>>>,
+            audio: &'a Option<AudioInfo>,
+        }
+
+        #[derive(serde::Serialize)]
+        #[serde(rename = "PictureInfo")]
+        struct PictureRef<'a> {
+            mime_type: &'a str,
+            description: &'a str,
+            picture_type: PictureType,
+            size: usize,
+        }
+
+        let metadata = self.0;
+        let pictures = metadata.pictures.as_ref().map(|pictures| {
+            pictures.iter()
+                .map(|p| PictureRef {
+                    mime_type: &p.mime_type,
+                    description: &p.description,
+                    picture_type: p.picture_type,
+                    size: p.size,
+                })
+                .collect()
+        });
+        MetadataRef {
+            tag: &metadata.tag,
+            properties: &metadata.properties,
+            pictures,
+            audio: &metadata.audio,
+        }.serialize(serializer)
+    }
+}
+
+impl<'a> From<&Tag<'a>> for TagInfo {
+    fn from(tag: &Tag<'a>) -> TagInfo {
+        TagInfo {
+            title: tag.title(),
+            artist: tag.artist(),
+            album: tag.album(),
+            comment: tag.comment(),
+            genre: tag.genre(),
+            year: tag.year(),
+            track: tag.track(),
+        }
+    }
+}
+
+/// An owned copy of the values of a `taglib::AudioProperties`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AudioInfo {
+    /// The length of the track, with millisecond precision
+    pub length: Duration,
+    /// The bit rate, in kB/s
+    pub bitrate: u32,
+    /// The sample rate, in Hz
+    pub samplerate: u32,
+    /// The number of audio channels
+    pub channels: u32,
+}
+
+impl<'a> From<&AudioProperties<'a>> for AudioInfo {
+    fn from(properties: &AudioProperties<'a>) -> AudioInfo {
+        AudioInfo {
+            length: properties.duration(),
+            bitrate: properties.bitrate(),
+            samplerate: properties.samplerate(),
+            channels: properties.channels(),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod test {
+    use super::*;
+    use super::super::FileType;
+
+    fn metadata() -> Metadata {
+        let mut properties = PropertyMap::new();
+        properties.insert("ARTIST".to_owned(), vec!["Artist".to_owned()]);
+        Metadata {
+            tag: Some(TagInfo { artist: Some("Artist".to_owned()), ..TagInfo::default() }),
+            properties: Some(properties),
+            pictures: Some(vec![PictureInfo::from(Picture {
+                mime_type: "image/png".to_owned(),
+                description: "Cover".to_owned(),
+                picture_type: PictureType::FrontCover,
+                data: b"\x89PNG".to_vec(),
+            })]),
+            audio: Some(AudioInfo {
+                length: Duration::from_millis(1500),
+                bitrate: 128,
+                samplerate: 44100,
+                channels: 2,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let metadata = metadata();
+        let json = serde_json::to_string(&metadata).unwrap();
+        assert!(json.contains(r#""picture_type":"FrontCover","size":4,"data":"iVBORw==""#));
+        assert_eq!(serde_json::from_str::<Metadata>(&json).unwrap(), metadata);
+    }
+
+    #[test]
+    fn test_serde_without_picture_data() {
+        let metadata = metadata();
+        let json = serde_json::to_string(&metadata.without_picture_data()).unwrap();
+        assert!(!json.contains("data"));
+        let read = serde_json::from_str::<Metadata>(&json).unwrap();
+        assert_eq!(read.pictures.as_ref().unwrap()[0].data, None);
+        assert!(metadata.diff(&read).is_empty());
+
+        // pictures described without data serialize the same way
+        let mut described = metadata.clone();
+        described.pictures = described.pictures
+            .map(|pictures| pictures.into_iter().map(PictureInfo::without_data).collect());
+        assert_eq!(serde_json::to_string(&described).unwrap(), json);
+
+        // empty data is serialized like any other
+        let mut empty = metadata.clone();
+        empty.pictures.as_mut().unwrap()[0].data = Some(Vec::new());
+        let json = serde_json::to_string(&empty).unwrap();
+        assert!(json.contains(r#""data":""}"#));
+        assert_eq!(serde_json::from_str::<Metadata>(&json).unwrap(), empty);
+    }
+
+    #[test]
+    fn test_serde_file_type() {
+        assert_eq!(serde_json::to_string(&FileType::OggVorbis).unwrap(), r#""OggVorbis""#);
+        assert_eq!(serde_json::from_str::<FileType>(r#""FLAC""#).unwrap(), FileType::FLAC);
+    }
+}
@@ -0,0 +1,415 @@
+// Copyright 2015  Emmanuele Bassi. All rights reserved.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+
+use std::{fmt, slice};
+use std::str::FromStr;
+
+use sys as ll;
+
+use super::{c_str_to_string, ComplexProperty, FileError, ParsePictureTypeError, Variant};
+
+// Keys of the attributes of a "PICTURE" complex property
+const KEY_DATA: &str = "data";
+const KEY_MIME_TYPE: &str = "mimeType";
+const KEY_DESCRIPTION: &str = "description";
+const KEY_PICTURE_TYPE: &str = "pictureType";
+
+/// The type of an embedded picture, as defined for ID3v2 APIC frames and
+/// FLAC picture blocks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PictureType {
+    /// A type not enumerated below
+    Other = 0x00,
+    /// 32x32 PNG image that should be used as the file icon
+    FileIcon = 0x01,
+    /// File icon of a different size or format
+    OtherFileIcon = 0x02,
+    /// Front cover image of the album
+    FrontCover = 0x03,
+    /// Back cover image of the album
+    BackCover = 0x04,
+    /// Inside leaflet page of the album
+    LeafletPage = 0x05,
+    /// Image from the album itself
+    Media = 0x06,
+    /// Picture of the lead artist or soloist
+    LeadArtist = 0x07,
+    /// Picture of the artist or performer
+    Artist = 0x08,
+    /// Picture of the conductor
+    Conductor = 0x09,
+    /// Picture of the band or orchestra
+    Band = 0x0A,
+    /// Picture of the composer
+    Composer = 0x0B,
+    /// Picture of the lyricist or text writer
+    Lyricist = 0x0C,
+    /// Picture of the recording location or studio
+    RecordingLocation = 0x0D,
+    /// Picture of the artists during recording
+    DuringRecording = 0x0E,
+    /// Picture of the artists during performance
+    DuringPerformance = 0x0F,
+    /// Picture from a movie or video related to the track
+    MovieScreenCapture = 0x10,
+    /// Picture of a large, coloured fish
+    ColoredFish = 0x11,
+    /// Illustration related to the track
+    Illustration = 0x12,
+    /// Logo of the band or performer
+    BandLogo = 0x13,
+    /// Logo of the publisher (record company)
+    PublisherLogo = 0x14,
+}
+
+impl PictureType {
+    /// Returns all picture types, ordered by their numeric value.
+    pub fn all() -> &'static [PictureType] {
+        &[
+            PictureType::Other,
+            PictureType::FileIcon,
+            PictureType::OtherFileIcon,
+            PictureType::FrontCover,
+            PictureType::BackCover,
+            PictureType::LeafletPage,
+            PictureType::Media,
+            PictureType::LeadArtist,
+            PictureType::Artist,
+            PictureType::Conductor,
+            PictureType::Band,
+            PictureType::Composer,
+            PictureType::Lyricist,
+            PictureType::RecordingLocation,
+            PictureType::DuringRecording,
+            PictureType::DuringPerformance,
+            PictureType::MovieScreenCapture,
+            PictureType::ColoredFish,
+            PictureType::Illustration,
+            PictureType::BandLogo,
+            PictureType::PublisherLogo,
+        ]
+    }
+
+    /// Returns the name TagLib uses for the picture type, e.g. "Front Cover".
+    pub fn name(&self) -> &'static str {
+        match self {
+            PictureType::Other => "Other",
+            PictureType::FileIcon => "File Icon",
+            PictureType::OtherFileIcon => "Other File Icon",
+            PictureType::FrontCover => "Front Cover",
+            PictureType::BackCover => "Back Cover",
+            PictureType::LeafletPage => "Leaflet Page",
+            PictureType::Media => "Media",
+            PictureType::LeadArtist => "Lead Artist",
+            PictureType::Artist => "Artist",
+            PictureType::Conductor => "Conductor",
+            PictureType::Band => "Band",
+            PictureType::Composer => "Composer",
+            PictureType::Lyricist => "Lyricist",
+            PictureType::RecordingLocation => "Recording Location",
+            PictureType::DuringRecording => "During Recording",
+            PictureType::DuringPerformance => "During Performance",
+            PictureType::MovieScreenCapture => "Movie Screen Capture",
+            PictureType::ColoredFish => "Coloured Fish",
+            PictureType::Illustration => "Illustration",
+            PictureType::BandLogo => "Band Logo",
+            PictureType::PublisherLogo => "Publisher Logo",
+        }
+    }
+
+    /// Returns the picture type with the given name, as returned by `name()`.
+    pub fn from_name(name: &str) -> Option<PictureType> {
+        PictureType::all().iter().find(|t| t.name() == name).copied()
+    }
+
+    /// Returns the picture type with the given numeric value.
+    pub fn from_id(id: u8) -> Option<PictureType> {
+        PictureType::all().get(id as usize).copied()
+    }
+}
+
+impl fmt::Display for PictureType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for PictureType {
+    type Err = ParsePictureTypeError;
+
+    /// Parses the name of a picture type, as returned by `name()`, ignoring
+    /// case.
+    fn from_str(s: &str) -> Result<PictureType, ParsePictureTypeError> {
+        PictureType::all()
+            .iter()
+            .find(|t| t.name().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| ParsePictureTypeError(s.to_owned()))
+    }
+}
+
+/// An image format, detected from the leading bytes of the image data.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ImageFormat {
+    JPEG,
+    PNG,
+    GIF,
+    WebP,
+    BMP,
+}
+
+impl ImageFormat {
+    /// Detects the image format from the magic bytes at the start of `data`.
+    pub fn detect(data: &[u8]) -> Option<ImageFormat> {
+        if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(ImageFormat::JPEG)
+        } else if data.starts_with(b"\x89PNG\r\n\x1A\n") {
+            Some(ImageFormat::PNG)
+        } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+            Some(ImageFormat::GIF)
+        } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+            Some(ImageFormat::WebP)
+        } else if data.starts_with(b"BM") {
+            Some(ImageFormat::BMP)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the MIME type of the image format.
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            ImageFormat::JPEG => "image/jpeg",
+            ImageFormat::PNG => "image/png",
+            ImageFormat::GIF => "image/gif",
+            ImageFormat::WebP => "image/webp",
+            ImageFormat::BMP => "image/bmp",
+        }
+    }
+
+    /// Returns whether `mime_type` describes this image format, accepting
+    /// common aliases such as "image/jpg".
+    pub fn matches_mime_type(&self, mime_type: &str) -> bool {
+        let mime_type = mime_type.trim().to_ascii_lowercase();
+        match self {
+            ImageFormat::JPEG => mime_type == "image/jpeg" || mime_type == "image/jpg",
+            ImageFormat::BMP => mime_type == "image/bmp" || mime_type == "image/x-ms-bmp",
+            _ => mime_type == self.mime_type(),
+        }
+    }
+}
+
+/// A picture embedded in an audio file, such as the cover art.
+///
+/// With the `serde` feature, the image data is serialized as a base64
+/// string.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Picture {
+    /// The MIME type of the image, e.g. "image/jpeg"
+    pub mime_type: String,
+    /// A description of the picture
+    pub description: String,
+    /// The type of the picture
+    pub picture_type: PictureType,
+    /// The image data
+    #[cfg_attr(feature = "serde", serde(with = "base64_data", default))]
+    pub data: Vec<u8>,
+}
+
+#[cfg(feature = "serde")]
+pub(crate) mod base64_data {
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        STANDARD.decode(s).map_err(de::Error::custom)
+    }
+
+    // the same, for image data that may be left out
+    pub mod option {
+        use base64::Engine;
+        use base64::engine::general_purpose::STANDARD;
+        use serde::{de, Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S>(data: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error>
+            where S: Serializer
+        {
+            match data {
+                Some(data) => serializer.serialize_some(&STANDARD.encode(data)),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error>
+            where D: Deserializer<'de>
+        {
+            Option::<String>::deserialize(deserializer)?
+                .map(|s| STANDARD.decode(s).map_err(de::Error::custom))
+                .transpose()
+        }
+    }
+}
+
+impl Picture {
+    /// Copies the picture out of the data filled in by
+    /// `taglib_picture_from_complex_property()`.
+    ///
+    /// Picture types TagLib has no name for, and the missing type of formats
+    /// such as MP4, are read as `PictureType::Other`.
+    pub(crate) unsafe fn from_raw(raw: &ll::TagLib_Complex_Property_Picture_Data)
+                                  -> Result<Picture, FileError> {
+        let data = if raw.data.is_null() {
+            Vec::new()
+        } else {
+            slice::from_raw_parts(raw.data as *const u8, raw.size as usize).to_vec()
+        };
+        Ok(Picture {
+            mime_type: c_str_to_string(raw.mime_type)?,
+            description: c_str_to_string(raw.description)?,
+            picture_type: PictureType::from_name(&c_str_to_string(raw.picture_type)?)
+                .unwrap_or(PictureType::Other),
+            data,
+        })
+    }
+
+    /// Returns the format of the image, detected from its content.
+    pub fn image_format(&self) -> Option<ImageFormat> {
+        ImageFormat::detect(&self.data)
+    }
+
+    /// Returns whether `mime_type` disagrees with the detected image format.
+    ///
+    /// Returns `false` if the image format cannot be detected.
+    pub fn has_mismatched_mime_type(&self) -> bool {
+        match self.image_format() {
+            Some(format) => !format.matches_mime_type(&self.mime_type),
+            None => false,
+        }
+    }
+
+    /// Sets `mime_type` to the MIME type of the detected image format.
+    ///
+    /// Returns `true` if `mime_type` was changed.
+    pub fn fix_mime_type(&mut self) -> bool {
+        if !self.has_mismatched_mime_type() {
+            return false;
+        }
+        match self.image_format() {
+            Some(format) => {
+                self.mime_type = format.mime_type().to_owned();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl From<&Picture> for ComplexProperty {
+    fn from(picture: &Picture) -> ComplexProperty {
+        let mut value = ComplexProperty::new();
+        value.insert(KEY_DATA.to_owned(), Variant::Bytes(picture.data.clone()));
+        value.insert(KEY_MIME_TYPE.to_owned(), Variant::String(picture.mime_type.clone()));
+        value.insert(KEY_DESCRIPTION.to_owned(), Variant::String(picture.description.clone()));
+        value.insert(KEY_PICTURE_TYPE.to_owned(), Variant::from(picture.picture_type.name()));
+        value
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::CString;
+    use std::{fs, ptr};
+
+    use super::*;
+
+    #[test]
+    fn test_picture_type_names() {
+        assert_eq!(PictureType::all().len(), 21);
+        for (i, t) in PictureType::all().iter().enumerate() {
+            assert_eq!(*t as usize, i);
+            assert_eq!(PictureType::from_id(i as u8), Some(*t));
+            assert_eq!(PictureType::from_name(t.name()), Some(*t));
+            assert_eq!(t.to_string(), t.name());
+            assert_eq!(t.name().parse::<PictureType>(), Ok(*t));
+            assert_eq!(t.name().to_uppercase().parse::<PictureType>(), Ok(*t));
+        }
+        // the names must match TagLib's, which uses British spelling
+        assert_eq!(PictureType::ColoredFish.name(), "Coloured Fish");
+        assert_eq!("Cover".parse::<PictureType>(),
+                   Err(ParsePictureTypeError("Cover".to_owned())));
+        assert_eq!(PictureType::from_name("Front Cover"), Some(PictureType::FrontCover));
+        assert_eq!(PictureType::from_name("Cover"), None);
+        assert_eq!(PictureType::from_id(0x15), None);
+    }
+
+    #[test]
+    fn test_from_raw_unknown_type() {
+        let mime_type = CString::new("image/png").unwrap();
+        let picture_type = CString::new("Band Photo").unwrap();
+        let raw = ll::TagLib_Complex_Property_Picture_Data {
+            mime_type: mime_type.as_ptr() as *mut _,
+            description: ptr::null_mut(),
+            picture_type: picture_type.as_ptr() as *mut _,
+            data: ptr::null_mut(),
+            size: 0,
+        };
+        let picture = unsafe { Picture::from_raw(&raw) }.unwrap();
+        assert_eq!(picture.picture_type, PictureType::Other);
+        assert_eq!(picture.mime_type, "image/png");
+    }
+
+    #[test]
+    fn test_image_format_detect() {
+        let jpeg = fs::read("fixtures/pic.jpg").unwrap();
+        assert_eq!(ImageFormat::detect(&jpeg), Some(ImageFormat::JPEG));
+        assert_eq!(ImageFormat::detect(b"\x89PNG\r\n\x1A\n\0\0"), Some(ImageFormat::PNG));
+        assert_eq!(ImageFormat::detect(b"GIF89a\x01\x00"), Some(ImageFormat::GIF));
+        assert_eq!(ImageFormat::detect(b"RIFF\0\0\0\0WEBPVP8 "), Some(ImageFormat::WebP));
+        assert_eq!(ImageFormat::detect(b"BM\0\0"), Some(ImageFormat::BMP));
+        assert_eq!(ImageFormat::detect(b"RIFF\0\0\0\0WAVE"), None);
+        assert_eq!(ImageFormat::detect(b""), None);
+    }
+
+    #[test]
+    fn test_fix_mime_type() {
+        let mut picture = Picture {
+            mime_type: "image/png".to_owned(),
+            description: String::new(),
+            picture_type: PictureType::FrontCover,
+            data: fs::read("fixtures/pic.jpg").unwrap(),
+        };
+        assert!(picture.has_mismatched_mime_type());
+        assert!(picture.fix_mime_type());
+        assert_eq!(picture.mime_type, "image/jpeg");
+        assert!(!picture.fix_mime_type());
+
+        picture.mime_type = "image/JPG".to_owned();
+        assert!(!picture.has_mismatched_mime_type());
+    }
+}
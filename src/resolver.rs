@@ -0,0 +1,147 @@
+// Copyright 2015  Emmanuele Bassi. All rights reserved.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::ffi::CStr;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Once, PoisonError, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use libc::{c_char, c_int};
+use sys as ll;
+
+use super::FileType;
+
+/// Chooses the type of files opened by name with `taglib::File::new()`,
+/// before TagLib's own detection.
+///
+/// Resolvers are added with `taglib::File::add_file_type_resolver()`.  They
+/// only see file names: files with an extension registered with
+/// `FileType::register_extension()` are opened as the registered type
+/// without asking them, and files read from a stream or from bytes are out
+/// of their scope; pass the type to `taglib::File::from_stream()` or
+/// `taglib::File::from_bytes()` instead.
+pub trait FileTypeResolver: Send + Sync {
+    /// Returns the type to open the file at `path` as, or `None` to leave
+    /// the choice to other resolvers and TagLib.
+    fn resolve(&self, path: &Path) -> Option<FileType>;
+}
+
+impl<F> FileTypeResolver for F
+    where F: Fn(&Path) -> Option<FileType> + Send + Sync
+{
+    fn resolve(&self, path: &Path) -> Option<FileType> {
+        self(path)
+    }
+}
+
+/// Identifies a resolver added with `taglib::File::add_file_type_resolver()`,
+/// to remove it with `taglib::File::remove_file_type_resolver()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ResolverId(u64);
+
+static RESOLVERS: RwLock<Vec<(ResolverId, Box<dyn FileTypeResolver>)>> = RwLock::new(Vec::new());
+
+pub(crate) fn add(resolver: Box<dyn FileTypeResolver>) -> ResolverId {
+    static INSTALL: Once = Once::new();
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+    let id = ResolverId(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    RESOLVERS.write().unwrap_or_else(PoisonError::into_inner).push((id, resolver));
+    // a single TagLib resolver calls all of ours, so that TagLib's list of
+    // resolvers, which is not thread-safe, only changes once
+    INSTALL.call_once(|| unsafe { ll::taglib_shim_add_file_type_resolver(resolve_file_type) });
+    id
+}
+
+// removes the resolver once no file is being resolved with it
+pub(crate) fn remove(id: ResolverId) -> bool {
+    let mut resolvers = RESOLVERS.write().unwrap_or_else(PoisonError::into_inner);
+    let len = resolvers.len();
+    resolvers.retain(|(i, _)| *i != id);
+    resolvers.len() != len
+}
+
+// like TagLib, tries the most recently added resolver first; as this runs
+// inside TagLib, a resolver that panics is skipped instead of unwinding
+fn resolve(path: &Path) -> Option<FileType> {
+    let resolvers = RESOLVERS.read().unwrap_or_else(PoisonError::into_inner);
+    resolvers.iter().rev().find_map(|(_, r)| {
+        panic::catch_unwind(AssertUnwindSafe(|| r.resolve(path))).ok().flatten()
+    })
+}
+
+#[cfg(unix)]
+fn to_path(filename: &CStr) -> Option<PathBuf> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    Some(PathBuf::from(OsStr::from_bytes(filename.to_bytes())))
+}
+
+// the shim passes file names as UTF-8 on other platforms
+#[cfg(not(unix))]
+fn to_path(filename: &CStr) -> Option<PathBuf> {
+    filename.to_str().ok().map(PathBuf::from)
+}
+
+unsafe extern "C" fn resolve_file_type(filename: *const c_char) -> c_int {
+    to_path(CStr::from_ptr(filename))
+        .and_then(|path| resolve(&path))
+        .map_or(-1, |t| t as c_int)
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::CString;
+
+    use super::*;
+
+    #[test]
+    fn test_resolve_file_type() {
+        add(Box::new(|path: &Path| {
+            match path.extension() {
+                Some(ext) if ext == "resolver-test" => Some(FileType::MP4),
+                _ => None,
+            }
+        }));
+        let flac = add(Box::new(|path: &Path| {
+            if path.file_name()? == "flac.resolver-test" { Some(FileType::FLAC) } else { None }
+        }));
+        add(Box::new(|path: &Path| {
+            if path.file_name()? == "panic.resolver-test" {
+                panic!("resolver failed");
+            }
+            None
+        }));
+
+        let resolve = |name: &str| unsafe {
+            resolve_file_type(CString::new(name).unwrap().as_ptr())
+        };
+        assert_eq!(resolve("dir/song.resolver-test"), FileType::MP4 as c_int);
+        assert_eq!(resolve("dir/flac.resolver-test"), FileType::FLAC as c_int);
+        assert_eq!(resolve("dir/panic.resolver-test"), FileType::MP4 as c_int);
+        assert_eq!(resolve("dir/song.mp3"), -1);
+
+        assert!(remove(flac));
+        assert!(!remove(flac));
+        assert_eq!(resolve("dir/flac.resolver-test"), FileType::MP4 as c_int);
+    }
+}
@@ -0,0 +1,241 @@
+// Copyright 2015  Emmanuele Bassi. All rights reserved.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+
+use std::fs;
+use std::io::{self, Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::slice;
+
+use libc::{c_char, c_int, c_longlong, c_void, size_t};
+use sys as ll;
+
+/// A stream that can be shortened.
+///
+/// Saving a file read through `taglib::File::from_stream()` may need to
+/// shorten the stream, for example when a tag gets smaller.
+pub trait Truncate {
+    /// Truncates the stream to `len` bytes.
+    fn truncate(&mut self, len: u64) -> io::Result<()>;
+}
+
+impl Truncate for fs::File {
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        self.set_len(len)
+    }
+}
+
+impl Truncate for Cursor<Vec<u8>> {
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        let len = len.try_into().map_err(|_| io::Error::from(ErrorKind::InvalidInput))?;
+        self.get_mut().truncate(len);
+        Ok(())
+    }
+}
+
+impl<T: Truncate + ?Sized> Truncate for Box<T> {
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        (**self).truncate(len)
+    }
+}
+
+// The operations TagLib needs from a stream
+trait StreamIo {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()>;
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64>;
+    fn truncate(&mut self, len: u64) -> io::Result<()>;
+}
+
+struct Reader<S>(S);
+
+impl<S: Read + Seek> StreamIo for Reader<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+
+    fn write_all(&mut self, _buf: &[u8]) -> io::Result<()> {
+        Err(ErrorKind::PermissionDenied.into())
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+
+    fn truncate(&mut self, _len: u64) -> io::Result<()> {
+        Err(ErrorKind::PermissionDenied.into())
+    }
+}
+
+struct ReadWriter<S>(S);
+
+impl<S: Read + Seek + Write + Truncate> StreamIo for ReadWriter<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.0.write_all(buf)
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        self.0.flush()?;
+        self.0.truncate(len)
+    }
+}
+
+/// A Rust stream made available to TagLib through the shim's callbacks.
+///
+/// The adapter lives behind the raw pointer returned on creation, which
+/// TagLib uses as the callback context, until `free()`; it must outlive the
+/// `TagLib_IOStream`.
+pub(crate) struct StreamAdapter {
+    io: Box<dyn StreamIo + Send>,
+    read_only: bool,
+    // TagLib drops the reason a callback failed, so keep the first one
+    error: Option<io::Error>,
+}
+
+impl StreamAdapter {
+    pub(crate) fn reader<S: Read + Seek + Send + 'static>(stream: S) -> *mut StreamAdapter {
+        StreamAdapter::into_raw(Box::new(Reader(stream)), true)
+    }
+
+    pub(crate) fn read_writer<S>(stream: S) -> *mut StreamAdapter
+        where S: Read + Seek + Write + Truncate + Send + 'static
+    {
+        StreamAdapter::into_raw(Box::new(ReadWriter(stream)), false)
+    }
+
+    fn into_raw(io: Box<dyn StreamIo + Send>, read_only: bool) -> *mut StreamAdapter {
+        Box::into_raw(Box::new(StreamAdapter { io, read_only, error: None }))
+    }
+
+    /// Creates a `TagLib_IOStream` reading from `adapter`.
+    pub(crate) unsafe fn iostream(adapter: *mut StreamAdapter) -> *mut ll::TagLib_IOStream {
+        let callbacks = ll::TagLib_Shim_Stream_Callbacks {
+            context: adapter as *mut c_void,
+            read: stream_read,
+            write: stream_write,
+            seek: stream_seek,
+            truncate: stream_truncate,
+            read_only: (*adapter).read_only as c_int,
+        };
+        ll::taglib_shim_iostream_new(&callbacks)
+    }
+
+    /// Returns the first error of the stream since the last call, if any.
+    /// `adapter` may be null.
+    pub(crate) unsafe fn take_error(adapter: *mut StreamAdapter) -> Option<io::Error> {
+        adapter.as_mut()?.error.take()
+    }
+
+    /// Frees `adapter`, which may be null, once its stream is freed.
+    pub(crate) unsafe fn free(adapter: *mut StreamAdapter) {
+        if !adapter.is_null() {
+            drop(Box::from_raw(adapter));
+        }
+    }
+}
+
+// Runs `f` on the stream behind `context`, keeping the error for
+// take_error(). Panics must not unwind into TagLib, so they become errors.
+unsafe fn with_stream<T, F>(context: *mut c_void, f: F) -> Option<T>
+    where F: FnOnce(&mut dyn StreamIo) -> io::Result<T>
+{
+    let io = &mut *(*(context as *mut StreamAdapter)).io;
+    let res = panic::catch_unwind(AssertUnwindSafe(|| f(io)))
+        .unwrap_or_else(|_| Err(io::Error::other("stream panicked")));
+    match res {
+        Ok(v) => Some(v),
+        Err(e) => {
+            record_error(context, e);
+            None
+        }
+    }
+}
+
+// Keeps `e` for take_error() unless an earlier error is pending.
+unsafe fn record_error(context: *mut c_void, e: io::Error) {
+    (*(context as *mut StreamAdapter)).error.get_or_insert(e);
+}
+
+unsafe extern "C" fn stream_read(context: *mut c_void,
+                                 data: *mut c_char,
+                                 length: size_t) -> c_longlong {
+    let buf = slice::from_raw_parts_mut(data as *mut u8, length);
+    let res = with_stream(context, |io| {
+        // TagLib treats a short read as the end of the stream
+        let mut total = 0;
+        while total < buf.len() {
+            match io.read(&mut buf[total..]) {
+                Ok(0) => break,
+                Ok(n) => total += n,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) if total > 0 => return Ok((total, Some(e))),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok((total, None))
+    });
+    match res {
+        Some((total, err)) => {
+            // Hand over the bytes already read, but still report the failure
+            if let Some(e) = err {
+                record_error(context, e);
+            }
+            total as c_longlong
+        }
+        None => -1,
+    }
+}
+
+unsafe extern "C" fn stream_write(context: *mut c_void,
+                                  data: *const c_char,
+                                  length: size_t) -> c_int {
+    let buf = slice::from_raw_parts(data as *const u8, length);
+    with_stream(context, |io| io.write_all(buf)).map_or(-1, |()| 0)
+}
+
+unsafe extern "C" fn stream_seek(context: *mut c_void,
+                                 offset: c_longlong,
+                                 whence: c_int) -> c_longlong {
+    let pos = match whence {
+        0 if offset >= 0 => SeekFrom::Start(offset as u64),
+        1 => SeekFrom::Current(offset),
+        2 => SeekFrom::End(offset),
+        _ => return -1,
+    };
+    with_stream(context, |io| io.seek(pos))
+        .and_then(|p| p.try_into().ok())
+        .unwrap_or(-1)
+}
+
+unsafe extern "C" fn stream_truncate(context: *mut c_void, length: c_longlong) -> c_int {
+    let len = match length.try_into() {
+        Ok(len) => len,
+        Err(_) => return -1,
+    };
+    with_stream(context, |io| io.truncate(len)).map_or(-1, |()| 0)
+}
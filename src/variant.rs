@@ -0,0 +1,315 @@
+// Copyright 2015  Emmanuele Bassi. All rights reserved.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+use std::ffi::CString;
+use std::{ptr, slice};
+use std::str::Utf8Error;
+
+use libc::c_char;
+use sys as ll;
+
+use super::{c_str_to_string, str_to_c_string};
+
+/// A value stored in an attribute of a complex property.
+///
+/// This is the owned counterpart of `TagLib_Variant`; use `Variant::from_raw()`
+/// and `Variant::to_raw()` to convert between the two.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Variant {
+    Void,
+    Bool(bool),
+    Int(i32),
+    UInt(u32),
+    LongLong(i64),
+    ULongLong(u64),
+    Double(f64),
+    String(String),
+    StringList(Vec<String>),
+    Bytes(Vec<u8>),
+}
+
+impl Variant {
+    /// Returns the `TagLib_Variant_Type` matching this value.
+    pub fn variant_type(&self) -> ll::TagLib_Variant_Type {
+        match self {
+            Variant::Void => ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_VOID,
+            Variant::Bool(_) => ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_BOOL,
+            Variant::Int(_) => ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_INT,
+            Variant::UInt(_) => ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_UINT,
+            Variant::LongLong(_) => ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_LONG_LONG,
+            Variant::ULongLong(_) => ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_ULONG_LONG,
+            Variant::Double(_) => ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_DOUBLE,
+            Variant::String(_) => ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_STRING,
+            Variant::StringList(_) => ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_STRING_LIST,
+            Variant::Bytes(_) => ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_BYTE_VECTOR,
+        }
+    }
+
+    /// Returns the string, if this is a `Variant::String`.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Variant::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns the bytes, if this is a `Variant::Bytes`.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Variant::Bytes(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    /// Copies the value out of a `TagLib_Variant`.
+    ///
+    /// Byte vectors are read using the `size` field and may contain NUL
+    /// bytes; strings must be valid UTF-8. Unknown types are read as
+    /// `Variant::Void`.
+    ///
+    /// # Safety
+    ///
+    /// The pointers in `raw` must be valid for the type stored in it.
+    pub unsafe fn from_raw(raw: &ll::TagLib_Variant) -> Result<Variant, Utf8Error> {
+        let value = &raw.value;
+        let res = match raw.r#type {
+            ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_BOOL => Variant::Bool(value.bool_value != 0),
+            ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_INT => Variant::Int(value.int_value),
+            ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_UINT => Variant::UInt(value.u_int_value),
+            ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_LONG_LONG => {
+                Variant::LongLong(value.long_long_value)
+            }
+            ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_ULONG_LONG => {
+                Variant::ULongLong(value.u_long_long_value)
+            }
+            ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_DOUBLE => Variant::Double(value.double_value),
+            ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_STRING => {
+                Variant::String(c_str_to_string(value.string_value)?)
+            }
+            ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_STRING_LIST => {
+                let mut list = Vec::new();
+                let mut p = value.string_list_value;
+                if !p.is_null() {
+                    while !(*p).is_null() {
+                        list.push(c_str_to_string(*p)?);
+                        p = p.add(1);
+                    }
+                }
+                Variant::StringList(list)
+            }
+            ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_BYTE_VECTOR => {
+                let data = value.byte_vector_value;
+                if data.is_null() || raw.size == 0 {
+                    Variant::Bytes(Vec::new())
+                } else {
+                    Variant::Bytes(slice::from_raw_parts(data as *const u8,
+                                                         raw.size as usize).to_vec())
+                }
+            }
+            _ => Variant::Void,
+        };
+        Ok(res)
+    }
+
+    /// Converts the value into a `TagLib_Variant` that can be passed to TagLib.
+    ///
+    /// Returns `None` if a string contains an interior NUL byte or cannot be
+    /// encoded, see `taglib::config::set_strings_unicode()`, or if a byte
+    /// vector is too large for the `size` field.
+    pub fn to_raw(&self) -> Option<RawVariant> {
+        RawVariant::new(self)
+    }
+}
+
+impl From<bool> for Variant {
+    fn from(v: bool) -> Variant {
+        Variant::Bool(v)
+    }
+}
+
+impl From<i32> for Variant {
+    fn from(v: i32) -> Variant {
+        Variant::Int(v)
+    }
+}
+
+impl From<u32> for Variant {
+    fn from(v: u32) -> Variant {
+        Variant::UInt(v)
+    }
+}
+
+impl From<i64> for Variant {
+    fn from(v: i64) -> Variant {
+        Variant::LongLong(v)
+    }
+}
+
+impl From<u64> for Variant {
+    fn from(v: u64) -> Variant {
+        Variant::ULongLong(v)
+    }
+}
+
+impl From<f64> for Variant {
+    fn from(v: f64) -> Variant {
+        Variant::Double(v)
+    }
+}
+
+impl From<&str> for Variant {
+    fn from(v: &str) -> Variant {
+        Variant::String(v.to_owned())
+    }
+}
+
+impl From<String> for Variant {
+    fn from(v: String) -> Variant {
+        Variant::String(v)
+    }
+}
+
+impl From<Vec<String>> for Variant {
+    fn from(v: Vec<String>) -> Variant {
+        Variant::StringList(v)
+    }
+}
+
+impl From<Vec<u8>> for Variant {
+    fn from(v: Vec<u8>) -> Variant {
+        Variant::Bytes(v)
+    }
+}
+
+/// A `TagLib_Variant` together with the buffers its pointers refer to.
+///
+/// Instances of `RawVariant` can only be created through the
+/// `taglib::Variant::to_raw()` method. The raw value is only valid as long
+/// as the `RawVariant` is alive.
+pub struct RawVariant {
+    raw: ll::TagLib_Variant,
+    _strings: Vec<CString>,
+    _list: Vec<*mut c_char>,
+    _bytes: Vec<u8>,
+}
+
+impl RawVariant {
+    fn new(variant: &Variant) -> Option<RawVariant> {
+        let mut strings = Vec::new();
+        let mut list = Vec::new();
+        let mut bytes = Vec::new();
+        let mut size = 0;
+
+        let (ty, value) = match variant {
+            Variant::Void => (ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_VOID,
+                              ll::TagLib_Variant_Value_Union { int_value: 0 }),
+            Variant::Bool(v) => (ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_BOOL,
+                                 ll::TagLib_Variant_Value_Union { bool_value: *v as i32 }),
+            Variant::Int(v) => (ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_INT,
+                                ll::TagLib_Variant_Value_Union { int_value: *v }),
+            Variant::UInt(v) => (ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_UINT,
+                                 ll::TagLib_Variant_Value_Union { u_int_value: *v }),
+            Variant::LongLong(v) => (ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_LONG_LONG,
+                                     ll::TagLib_Variant_Value_Union { long_long_value: *v }),
+            Variant::ULongLong(v) => (ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_ULONG_LONG,
+                                      ll::TagLib_Variant_Value_Union { u_long_long_value: *v }),
+            Variant::Double(v) => (ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_DOUBLE,
+                                   ll::TagLib_Variant_Value_Union { double_value: *v }),
+            Variant::String(s) => {
+                let cs = str_to_c_string(s)?;
+                let p = cs.as_ptr() as *mut c_char;
+                strings.push(cs);
+                (ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_STRING,
+                 ll::TagLib_Variant_Value_Union { string_value: p })
+            }
+            Variant::StringList(l) => {
+                for s in l {
+                    strings.push(str_to_c_string(s)?);
+                }
+                list.extend(strings.iter().map(|cs| cs.as_ptr() as *mut c_char));
+                list.push(ptr::null_mut());
+                (ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_STRING_LIST,
+                 ll::TagLib_Variant_Value_Union { string_list_value: list.as_mut_ptr() })
+            }
+            Variant::Bytes(b) => {
+                size = b.len().try_into().ok()?;
+                bytes.extend_from_slice(b);
+                (ll::TAGLIB_VARIANT_TYPE_TAGLIB_VARIANT_BYTE_VECTOR,
+                 ll::TagLib_Variant_Value_Union {
+                     byte_vector_value: bytes.as_mut_ptr() as *mut c_char
+                 })
+            }
+        };
+
+        Some(RawVariant {
+            raw: ll::TagLib_Variant { r#type: ty, size, value },
+            _strings: strings,
+            _list: list,
+            _bytes: bytes,
+        })
+    }
+
+    /// Returns the `TagLib_Variant`.
+    pub fn as_raw(&self) -> &ll::TagLib_Variant {
+        &self.raw
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn round_trip(v: Variant) {
+        let raw = v.to_raw().unwrap();
+        assert_eq!(raw.as_raw().r#type, v.variant_type());
+        assert_eq!(unsafe { Variant::from_raw(raw.as_raw()) }.unwrap(), v);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip(Variant::Void);
+        round_trip(Variant::Bool(true));
+        round_trip(Variant::Int(-42));
+        round_trip(Variant::UInt(u32::MAX));
+        round_trip(Variant::LongLong(i64::MIN));
+        round_trip(Variant::ULongLong(u64::MAX));
+        round_trip(Variant::Double(0.25));
+        round_trip(Variant::String("Tïtle".to_owned()));
+        round_trip(Variant::String(String::new()));
+        round_trip(Variant::StringList(vec!["a".to_owned(), "".to_owned(), "c".to_owned()]));
+        round_trip(Variant::StringList(vec![]));
+        round_trip(Variant::Bytes(vec![0, 1, 0, 255]));
+        round_trip(Variant::Bytes(vec![]));
+    }
+
+    #[test]
+    fn test_byte_vector_size() {
+        let raw = Variant::Bytes(vec![0; 5]).to_raw().unwrap();
+        assert_eq!(raw.as_raw().size, 5);
+        let raw = Variant::String("abc".to_owned()).to_raw().unwrap();
+        assert_eq!(raw.as_raw().size, 0);
+    }
+
+    #[test]
+    fn test_interior_nul() {
+        assert!(Variant::String("a\0b".to_owned()).to_raw().is_none());
+        assert!(Variant::StringList(vec!["a\0b".to_owned()]).to_raw().is_none());
+    }
+}
@@ -1,5 +1,6 @@
 #[cfg(feature = "pkg-config")]
 extern crate pkg_config;
+extern crate cc;
 extern crate core;
 
 use std::collections::HashSet;
@@ -9,6 +10,15 @@ use std::path::PathBuf;
 const KEY_TAGLIB_STATIC: &'static str = "TAGLIB_STATIC";
 const KEY_TAGLIB_DIRS: &'static str = "TAGLIB_LIB_DIRS";
 const KEY_TAGLIB_EXTRA_LIBS: &'static str = "TAGLIB_EXTRA_LIBS";
+const KEY_TAGLIB_INCLUDE_DIRS: &'static str = "TAGLIB_INCLUDE_DIRS";
+
+// the shim uses complex properties and 64-bit offsets, added in TagLib 2.0
+#[cfg(feature = "pkg-config")]
+const MIN_TAGLIB_VERSION: &str = "2.0";
+
+const SHIM_SOURCE: &'static str = "src/shim.cpp";
+const DEFAULT_INCLUDE_DIRS: [&'static str; 2] = ["/usr/include/taglib",
+                                                 "/usr/local/include/taglib"];
 
 // if not empty and not zero, build as static link, default is dynamic link (dll/so/dylib), example:
 // TAGLIB_STATIC=1
@@ -16,13 +26,29 @@ const KEY_TAGLIB_EXTRA_LIBS: &'static str = "TAGLIB_EXTRA_LIBS";
 // TAGLIB_LIB_DIRS=/others/lib:/opt/usr/local/lib
 // multiple name separated by char `:`, example:
 // TAGLIB_EXTRA_LIBS=zlib
+// directories containing the TagLib headers (tag_c.h, fileref.h, ...), separated like
+// TAGLIB_LIB_DIRS, default is /usr/include/taglib and /usr/local/include/taglib, example:
+// TAGLIB_INCLUDE_DIRS=/opt/usr/local/include/taglib
 fn main() {
-    if !build_pkgconfig() {
-        build_env();
-    }
+    let include_dirs = match build_pkgconfig() {
+        Some(dirs) => dirs,
+        None => build_env(),
+    };
+    build_shim(&include_dirs);
+}
+
+// the shim exposes the parts of the TagLib C++ API that tag_c does not cover
+fn build_shim(include_dirs: &[PathBuf]) {
+    println!("cargo:rerun-if-changed={}", SHIM_SOURCE);
+    cc::Build::new()
+        .cpp(true)
+        .std("c++17")
+        .includes(include_dirs)
+        .file(SHIM_SOURCE)
+        .compile("taglib_shim");
 }
 
-fn build_env() {
+fn build_env() -> Vec<PathBuf> {
     let sep = get_sep();
 
     let lib_dirs = get_lib_dirs(sep);
@@ -41,6 +67,17 @@ fn build_env() {
 
     println!("cargo:rustc-link-lib={}={}", kind, "tag_c");
     println!("cargo:rustc-link-lib={}={}", kind, "tag");
+
+    get_include_dirs(sep)
+}
+
+fn get_include_dirs(sep: char) -> Vec<PathBuf> {
+    let dirs = get_env_hashset_string(KEY_TAGLIB_INCLUDE_DIRS, sep).into_iter()
+        .map(PathBuf::from).collect::<Vec<PathBuf>>();
+    if !dirs.is_empty() {
+        return dirs;
+    }
+    DEFAULT_INCLUDE_DIRS.iter().map(PathBuf::from).filter(|d| d.exists()).collect()
 }
 
 fn get_extra_libs() -> HashSet<String> {
@@ -86,14 +123,21 @@ fn get_env_string(env_key: &str) -> Option<String> {
 }
 
 #[cfg(not(feature = "pkg-config"))]
-fn build_pkgconfig() -> bool {
-    false
+fn build_pkgconfig() -> Option<Vec<PathBuf>> {
+    None
 }
 
 #[cfg(feature = "pkg-config")]
-fn build_pkgconfig() -> bool {
-    if pkg_config::find_library("taglib_c").is_err() {
-        panic!("Could not find taglib_c via pkgconfig");
-    }
-    true
+fn build_pkgconfig() -> Option<Vec<PathBuf>> {
+    let tag_c = match pkg_config::Config::new().atleast_version(MIN_TAGLIB_VERSION).probe("taglib_c") {
+        Ok(lib) => lib,
+        Err(e) => panic!("Could not find taglib_c >= {} via pkgconfig: {}", MIN_TAGLIB_VERSION, e),
+    };
+    let tag = match pkg_config::Config::new().atleast_version(MIN_TAGLIB_VERSION).probe("taglib") {
+        Ok(lib) => lib,
+        Err(e) => panic!("Could not find taglib >= {} via pkgconfig: {}", MIN_TAGLIB_VERSION, e),
+    };
+    let mut dirs = tag_c.include_paths;
+    dirs.extend(tag.include_paths);
+    Some(dirs)
 }
@@ -21,12 +21,13 @@
 #![allow(non_camel_case_types)]
 extern crate libc;
 
-use libc::{c_int, c_uint, c_char, c_void, c_longlong, c_ulonglong};
+use libc::{c_int, c_uint, c_char, c_void, c_longlong, c_ulonglong, size_t};
 
 // Public types; these are all opaque pointer types
 pub type TagLib_File = c_void;
 pub type TagLib_Tag = c_void;
 pub type TagLib_AudioProperties = c_void;
+pub type TagLib_IOStream = c_void;
 
 pub type TagLib_Bool = c_int;
 pub type TagLib_FileType = c_uint;
@@ -52,6 +53,13 @@ pub const TAGLIB_FILE_OPUS: TagLib_FileType = 17;
 pub const TAGLIB_FILE_DSF: TagLib_FileType = 18;
 pub const TAGLIB_FILE_DSDIFF: TagLib_FileType = 19;
 
+pub type TagLib_ID3v2_Encoding = c_uint;
+
+pub const TAGLIB_ID3V2_LATIN1: TagLib_ID3v2_Encoding = 0;
+pub const TAGLIB_ID3V2_UTF16: TagLib_ID3v2_Encoding = 1;
+pub const TAGLIB_ID3V2_UTF16BE: TagLib_ID3v2_Encoding = 2;
+pub const TAGLIB_ID3V2_UTF8: TagLib_ID3v2_Encoding = 3;
+
 // tag_c.h
 extern "C" {
     pub fn taglib_file_new(filename: *const c_char) -> *mut TagLib_File;
@@ -60,11 +68,15 @@ extern "C" {
         filetype: TagLib_FileType,
     ) -> *mut TagLib_File;
     pub fn taglib_file_is_valid(file: *mut TagLib_File) -> TagLib_Bool;
+    pub fn taglib_file_new_iostream(stream: *mut TagLib_IOStream) -> *mut TagLib_File;
     pub fn taglib_file_free(file: *mut TagLib_File);
     pub fn taglib_file_save(file: *mut TagLib_File) -> TagLib_Bool;
     pub fn taglib_file_tag(file: *mut TagLib_File) -> *mut TagLib_Tag;
     pub fn taglib_file_audioproperties(file: *mut TagLib_File) -> *const TagLib_AudioProperties;
 
+    pub fn taglib_memory_iostream_new(data: *const c_char, size: c_uint) -> *mut TagLib_IOStream;
+    pub fn taglib_iostream_free(stream: *mut TagLib_IOStream);
+
     pub fn taglib_tag_title(tag: *const TagLib_Tag) -> *const c_char;
     pub fn taglib_tag_artist(tag: *const TagLib_Tag) -> *const c_char;
     pub fn taglib_tag_album(tag: *const TagLib_Tag) -> *const c_char;
@@ -81,6 +93,23 @@ extern "C" {
     pub fn taglib_tag_set_track(tag: *mut TagLib_Tag, track: c_uint);
     pub fn taglib_tag_free_strings();
 
+    #[doc = " By default all strings coming into or out of TagLib's C API are managed\n \
+    by TagLib and freed by taglib_tag_free_strings().  If \\a management is\n \
+    false, the strings returned by the taglib_tag_*() getters must be freed\n \
+    by the client using taglib_free()."]
+    pub fn taglib_set_string_management_enabled(management: TagLib_Bool);
+
+    #[doc = " By default all strings coming into or out of TagLib's C API are in UTF8.\n \
+    However, it may be desirable for TagLib to operate on Latin1 (ISO-8859-1)\n \
+    strings in which case this should be set to FALSE."]
+    pub fn taglib_set_strings_unicode(unicode: TagLib_Bool);
+
+    #[doc = " This sets the default encoding for ID3v2 frames that are written to tags."]
+    pub fn taglib_id3v2_set_default_text_encoding(encoding: TagLib_ID3v2_Encoding);
+
+    #[doc = " Explicitly free a string returned from TagLib."]
+    pub fn taglib_free(pointer: *mut c_void);
+
     #[doc = " Get the keys of the property map.\n\n \
     \\return NULL terminated array of C-strings (char *), only NULL if empty.\n \
     It must be freed by the client using taglib_property_free()."]
@@ -233,3 +262,129 @@ extern "C" {
     C-strings and byte vectors contained in these attributes."]
     pub fn taglib_complex_property_free(props: *mut *mut *mut TagLib_Complex_Property_Attribute);
 }
+
+// shim.cpp
+#[doc = " Callbacks of a stream implemented by the client.\n\n \
+\\e read returns the number of bytes read, \\e seek the new position (\\e whence\n \
+is 0, 1 or 2 for the beginning, current position or end), \\e write and\n \
+\\e truncate return 0. All callbacks return -1 on error."]
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct TagLib_Shim_Stream_Callbacks {
+    pub context: *mut c_void,
+    pub read: unsafe extern "C" fn(context: *mut c_void, data: *mut c_char, length: size_t)
+                                   -> c_longlong,
+    pub write: unsafe extern "C" fn(context: *mut c_void, data: *const c_char, length: size_t)
+                                    -> c_int,
+    pub seek: unsafe extern "C" fn(context: *mut c_void, offset: c_longlong, whence: c_int)
+                                   -> c_longlong,
+    pub truncate: unsafe extern "C" fn(context: *mut c_void, length: c_longlong) -> c_int,
+    pub read_only: c_int,
+}
+
+#[doc = " Returns the TagLib_File_Type to open the named file as, or -1 to leave\n \
+the choice to TagLib."]
+pub type TagLib_Shim_Resolve_Callback = unsafe extern "C" fn(filename: *const c_char) -> c_int;
+
+extern "C" {
+    #[doc = " Creates a stream calling \\a callbacks, which are copied.\n \
+    It must be freed by the client using taglib_iostream_free()."]
+    pub fn taglib_shim_iostream_new(
+        callbacks: *const TagLib_Shim_Stream_Callbacks,
+    ) -> *mut TagLib_IOStream;
+
+    #[doc = " Creates a file of type \\a filetype reading from \\a stream.\n\n \
+    The stream is not owned by the file and must outlive it."]
+    pub fn taglib_shim_file_new_iostream_type(
+        stream: *mut TagLib_IOStream,
+        filetype: TagLib_FileType,
+    ) -> *mut TagLib_File;
+
+    #[doc = " Returns whether TagLib found a parser for \\a file, even if the file\n \
+    turned out to be invalid."]
+    pub fn taglib_shim_file_has_file(file: *mut TagLib_File) -> TagLib_Bool;
+
+    #[doc = " Returns whether \\a file was opened read-only."]
+    pub fn taglib_shim_file_read_only(file: *mut TagLib_File) -> TagLib_Bool;
+
+    #[doc = " Saves \\a file like taglib_file_save() and stores the errno left by\n \
+    writing it in \\a error, or 0 if no write failed.\n\n \
+    TagLib does not check every write to files opened by name, so \\a error\n \
+    may be set even if saving seemed to succeed."]
+    pub fn taglib_shim_file_save(file: *mut TagLib_File, error: *mut c_int) -> TagLib_Bool;
+
+    #[doc = " Adds a resolver that calls \\a resolve to choose the type of files opened\n \
+    by name, before the resolvers added earlier and TagLib's own detection."]
+    pub fn taglib_shim_add_file_type_resolver(resolve: TagLib_Shim_Resolve_Callback);
+
+    #[doc = " Returns the length of the file in milliseconds."]
+    pub fn taglib_shim_audioproperties_length_ms(
+        properties: *const TagLib_AudioProperties,
+    ) -> c_int;
+
+    #[doc = " Stores the type of the TagLib::File subclass parsing \\a file in \\a type.\n\n \
+    \\return false if there is none or it is not one of the types of\n \
+    TagLib_File_Type."]
+    pub fn taglib_shim_file_type(file: *const TagLib_File,
+                                 filetype: *mut TagLib_FileType) -> TagLib_Bool;
+
+    #[doc = " Returns the current contents of a stream created by\n \
+    taglib_memory_iostream_new() and stores their length in \\a size, or\n \
+    returns NULL if \\a stream is of another kind.\n\n \
+    The data is owned by the stream and only valid until it is modified or freed."]
+    pub fn taglib_shim_memory_iostream_data(
+        stream: *mut TagLib_IOStream,
+        size: *mut c_uint,
+    ) -> *const c_char;
+    #[doc = " Get all properties of \\a file.\n\n \
+    \\return NULL terminated array of properties, each a NULL terminated array of\n \
+    C-strings holding the key followed by the values. Strings are UTF-8 if\n \
+    \\a unicode is true, Latin1 otherwise. It must be freed by the client using\n \
+    taglib_shim_property_map_free()."]
+    pub fn taglib_shim_property_map(
+        file: *const TagLib_File,
+        unicode: TagLib_Bool,
+    ) -> *mut *mut *mut c_char;
+
+    #[doc = " Replaces all properties of \\a file with \\a props, in the format returned\n \
+    by taglib_shim_property_map(); keys missing from \\a props are removed.\n\n \
+    \\return the properties which could not be set, in the same format. It must\n \
+    be freed by the client using taglib_shim_property_map_free()."]
+    pub fn taglib_shim_set_property_map(
+        file: *mut TagLib_File,
+        props: *const *const *const c_char,
+        unicode: TagLib_Bool,
+    ) -> *mut *mut *mut c_char;
+
+    #[doc = " Frees properties returned by taglib_shim_property_map()."]
+    pub fn taglib_shim_property_map_free(props: *mut *mut *mut c_char);
+
+    #[doc = " Get the meta-data of \\a file that cannot be represented as properties,\n \
+    such as unknown ID3v2 frames.\n\n \
+    \\return NULL terminated array of C-strings identifying the meta-data. It\n \
+    must be freed by the client using taglib_shim_string_list_free()."]
+    pub fn taglib_shim_unsupported_properties(
+        file: *const TagLib_File,
+        unicode: TagLib_Bool,
+    ) -> *mut *mut c_char;
+
+    #[doc = " Removes the meta-data identified by the NULL terminated array \\a props,\n \
+    as returned by taglib_shim_unsupported_properties(), from \\a file."]
+    pub fn taglib_shim_remove_unsupported_properties(
+        file: *mut TagLib_File,
+        props: *const *const c_char,
+        unicode: TagLib_Bool,
+    );
+
+    #[doc = " Replaces all values of the complex property \\a key with the NULL\n \
+    terminated array \\a values at once; an empty array removes the property."]
+    pub fn taglib_shim_complex_property_set_list(
+        file: *mut TagLib_File,
+        key: *const c_char,
+        values: *const *const *const TagLib_Complex_Property_Attribute,
+        unicode: TagLib_Bool,
+    ) -> TagLib_Bool;
+
+    #[doc = " Frees a string list returned by taglib_shim_unsupported_properties()."]
+    pub fn taglib_shim_string_list_free(list: *mut *mut c_char);
+}
@@ -0,0 +1,544 @@
+// Copyright 2015  Emmanuele Bassi. All rights reserved.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+// Small C wrappers around parts of the TagLib C++ API that tag_c does not
+// expose. A TagLib_File is a TagLib::FileRef and a TagLib_IOStream is a
+// TagLib::IOStream, as created by tag_c.
+
+#include <algorithm>
+#include <cerrno>
+#include <string>
+
+#include <tag_c.h>
+
+#include <fileref.h>
+#include <tpropertymap.h>
+#include <tbytevectorstream.h>
+#include <tvariant.h>
+
+#include <aifffile.h>
+#include <apefile.h>
+#include <asffile.h>
+#include <dsdifffile.h>
+#include <dsffile.h>
+#include <flacfile.h>
+#include <itfile.h>
+#include <modfile.h>
+#include <mp4file.h>
+#include <mpcfile.h>
+#include <mpegfile.h>
+#include <oggflacfile.h>
+#include <opusfile.h>
+#include <s3mfile.h>
+#include <speexfile.h>
+#include <trueaudiofile.h>
+#include <vorbisfile.h>
+#include <wavfile.h>
+#include <wavpackfile.h>
+#include <xmfile.h>
+
+extern "C" {
+
+// Callbacks of a stream implemented in Rust. read returns the number of bytes
+// read, seek the new position and the other callbacks 0, or -1 on error.
+typedef struct {
+  void *context;
+  long long (*read)(void *context, char *data, size_t length);
+  int (*write)(void *context, const char *data, size_t length);
+  long long (*seek)(void *context, long long offset, int whence);
+  int (*truncate)(void *context, long long length);
+  int read_only;
+} TagLib_Shim_Stream_Callbacks;
+
+// Returns the TagLib_File_Type to open the named file as, or -1 to leave the
+// choice to TagLib.
+typedef int (*TagLib_Shim_Resolve_Callback)(const char *fileName);
+
+}
+
+using namespace TagLib;
+
+namespace {
+
+// An IOStream forwarding to the callbacks of a Rust stream. Inserting and
+// removing data is implemented on top of reading, writing and truncating.
+class CallbackStream : public IOStream
+{
+public:
+  explicit CallbackStream(const TagLib_Shim_Stream_Callbacks &callbacks) :
+    cb(callbacks)
+  {
+  }
+
+  FileName name() const override
+  {
+    return "";
+  }
+
+  ByteVector readBlock(size_t length) override
+  {
+    ByteVector buffer(static_cast<unsigned int>(length), 0);
+    long long n = cb.read(cb.context, buffer.data(), length);
+    buffer.resize(n > 0 ? static_cast<unsigned int>(n) : 0);
+    return buffer;
+  }
+
+  void writeBlock(const ByteVector &data) override
+  {
+    if(readOnly())
+      return;
+    cb.write(cb.context, data.data(), data.size());
+  }
+
+  void insert(const ByteVector &data, offset_t start = 0, size_t replace = 0) override
+  {
+    if(readOnly())
+      return;
+
+    // a failed callback stops the insertion, the Rust side reports the error
+    const offset_t oldLength = length();
+    if(oldLength < 0)
+      return;
+    const offset_t restStart = std::min<offset_t>(start + replace, oldLength);
+    const offset_t restTarget = start + data.size();
+    if(restStart != restTarget && !moveBlock(restStart, oldLength, restTarget))
+      return;
+    if(!seekTo(start) || !write(data))
+      return;
+    if(restTarget < restStart)
+      truncate(oldLength - (restStart - restTarget));
+  }
+
+  void removeBlock(offset_t start = 0, size_t len = 0) override
+  {
+    insert(ByteVector(), start, len);
+  }
+
+  bool readOnly() const override
+  {
+    return cb.read_only != 0;
+  }
+
+  bool isOpen() const override
+  {
+    return true;
+  }
+
+  void seek(offset_t offset, Position p = Beginning) override
+  {
+    cb.seek(cb.context, offset, static_cast<int>(p));
+  }
+
+  offset_t tell() const override
+  {
+    return cb.seek(cb.context, 0, Current);
+  }
+
+  offset_t length() override
+  {
+    const offset_t current = tell();
+    const offset_t end = cb.seek(cb.context, 0, End);
+    seek(current, Beginning);
+    return end;
+  }
+
+  void truncate(offset_t length) override
+  {
+    if(readOnly())
+      return;
+    cb.truncate(cb.context, length);
+  }
+
+private:
+  bool seekTo(offset_t offset)
+  {
+    return cb.seek(cb.context, offset, Beginning) == offset;
+  }
+
+  bool write(const ByteVector &data)
+  {
+    return cb.write(cb.context, data.data(), data.size()) == 0;
+  }
+
+  // Moves the bytes from `from` up to `end` to `to`, one buffer at a time.
+  // When moving towards the end, the last buffer goes first so that nothing
+  // is overwritten before it was read.
+  bool moveBlock(offset_t from, offset_t end, offset_t to)
+  {
+    const offset_t bufferSize = 65536;
+    const bool backwards = to > from;
+    for(offset_t done = 0; done < end - from;) {
+      const offset_t n = std::min<offset_t>(bufferSize, end - from - done);
+      const offset_t offset = backwards ? end - done - n : from + done;
+      ByteVector buffer(static_cast<unsigned int>(n), 0);
+      if(!seekTo(offset) || cb.read(cb.context, buffer.data(), static_cast<size_t>(n)) != n)
+        return false;
+      if(!seekTo(offset - from + to) || !write(buffer))
+        return false;
+      done += n;
+    }
+    return true;
+  }
+
+  TagLib_Shim_Stream_Callbacks cb;
+};
+
+// Creates a file of the given type from a file name or a stream.
+template <class T>
+File *newFile(TagLib_File_Type type, T source, bool readAudioProperties,
+              AudioProperties::ReadStyle audioPropertiesStyle)
+{
+  switch(type) {
+  case TagLib_File_MPEG:
+    return new MPEG::File(source, readAudioProperties, audioPropertiesStyle);
+  case TagLib_File_OggVorbis:
+    return new Ogg::Vorbis::File(source, readAudioProperties, audioPropertiesStyle);
+  case TagLib_File_FLAC:
+    return new FLAC::File(source, readAudioProperties, audioPropertiesStyle);
+  case TagLib_File_MPC:
+    return new MPC::File(source, readAudioProperties, audioPropertiesStyle);
+  case TagLib_File_OggFlac:
+    return new Ogg::FLAC::File(source, readAudioProperties, audioPropertiesStyle);
+  case TagLib_File_WavPack:
+    return new WavPack::File(source, readAudioProperties, audioPropertiesStyle);
+  case TagLib_File_Speex:
+    return new Ogg::Speex::File(source, readAudioProperties, audioPropertiesStyle);
+  case TagLib_File_TrueAudio:
+    return new TrueAudio::File(source, readAudioProperties, audioPropertiesStyle);
+  case TagLib_File_MP4:
+    return new MP4::File(source, readAudioProperties, audioPropertiesStyle);
+  case TagLib_File_ASF:
+    return new ASF::File(source, readAudioProperties, audioPropertiesStyle);
+  case TagLib_File_AIFF:
+    return new RIFF::AIFF::File(source, readAudioProperties, audioPropertiesStyle);
+  case TagLib_File_WAV:
+    return new RIFF::WAV::File(source, readAudioProperties, audioPropertiesStyle);
+  case TagLib_File_APE:
+    return new APE::File(source, readAudioProperties, audioPropertiesStyle);
+  case TagLib_File_IT:
+    return new IT::File(source, readAudioProperties, audioPropertiesStyle);
+  case TagLib_File_Mod:
+    return new Mod::File(source, readAudioProperties, audioPropertiesStyle);
+  case TagLib_File_S3M:
+    return new S3M::File(source, readAudioProperties, audioPropertiesStyle);
+  case TagLib_File_XM:
+    return new XM::File(source, readAudioProperties, audioPropertiesStyle);
+  case TagLib_File_Opus:
+    return new Ogg::Opus::File(source, readAudioProperties, audioPropertiesStyle);
+  case TagLib_File_DSF:
+    return new DSF::File(source, readAudioProperties, audioPropertiesStyle);
+  case TagLib_File_DSDIFF:
+    return new DSDIFF::File(source, readAudioProperties, audioPropertiesStyle);
+  default:
+    return nullptr;
+  }
+}
+
+// Lets the client choose the type of a file from its name.
+class CallbackResolver : public FileRef::FileTypeResolver
+{
+public:
+  explicit CallbackResolver(TagLib_Shim_Resolve_Callback resolve) :
+    resolve(resolve)
+  {
+  }
+
+  File *createFile(FileName fileName, bool readAudioProperties,
+                   AudioProperties::ReadStyle audioPropertiesStyle) const override
+  {
+#ifdef _WIN32
+    const std::string name = String(fileName.wstr()).to8Bit(true);
+#else
+    const std::string name = fileName;
+#endif
+    const int type = resolve(name.c_str());
+    if(type < 0)
+      return nullptr;
+    return newFile(static_cast<TagLib_File_Type>(type), fileName, readAudioProperties,
+                   audioPropertiesStyle);
+  }
+
+private:
+  TagLib_Shim_Resolve_Callback resolve;
+};
+
+template <class T>
+bool isFile(const File *f)
+{
+  return dynamic_cast<const T *>(f) != nullptr;
+}
+
+// Copies a string for the client, encoded as tag_c does with
+// taglib_set_strings_unicode(unicode). It is freed with delete[].
+char *copyString(const String &s, bool unicode)
+{
+  const std::string str = s.to8Bit(unicode);
+  char *res = new char[str.size() + 1];
+  std::copy(str.c_str(), str.c_str() + str.size() + 1, res);
+  return res;
+}
+
+String toString(const char *s, bool unicode)
+{
+  return String(s, unicode ? String::UTF8 : String::Latin1);
+}
+
+// Converts a value of a complex property attribute, as tag_c does.
+Variant toVariant(const TagLib_Variant &v, bool unicode)
+{
+  switch(v.type) {
+  case TagLib_Variant_Bool:
+    return Variant(v.value.boolValue != 0);
+  case TagLib_Variant_Int:
+    return Variant(v.value.intValue);
+  case TagLib_Variant_UInt:
+    return Variant(v.value.uIntValue);
+  case TagLib_Variant_LongLong:
+    return Variant(v.value.longLongValue);
+  case TagLib_Variant_ULongLong:
+    return Variant(v.value.uLongLongValue);
+  case TagLib_Variant_Double:
+    return Variant(v.value.doubleValue);
+  case TagLib_Variant_String:
+    return Variant(toString(v.value.stringValue, unicode));
+  case TagLib_Variant_StringList: {
+    StringList list;
+    for(char **s = v.value.stringListValue; s && *s; ++s)
+      list.append(toString(*s, unicode));
+    return Variant(list);
+  }
+  case TagLib_Variant_ByteVector:
+    return Variant(ByteVector(v.value.byteVectorValue, v.size));
+  default:
+    return Variant();
+  }
+}
+
+// Copies a string list into a NULL terminated array, freed with
+// taglib_shim_string_list_free().
+char **copyStringList(const StringList &list, bool unicode)
+{
+  char **res = new char *[list.size() + 1];
+  char **p = res;
+  for(const auto &s : list)
+    *p++ = copyString(s, unicode);
+  *p = nullptr;
+  return res;
+}
+
+// Flattens a property map as described for taglib_shim_property_map().
+char ***copyPropertyMap(const PropertyMap &map, bool unicode)
+{
+  char ***props = new char **[map.size() + 1];
+  char ***p = props;
+  for(const auto &[key, values] : map) {
+    char **prop = new char *[values.size() + 2];
+    char **v = prop;
+    *v++ = copyString(key, unicode);
+    for(const auto &value : values)
+      *v++ = copyString(value, unicode);
+    *v = nullptr;
+    *p++ = prop;
+  }
+  *p = nullptr;
+  return props;
+}
+
+}
+
+extern "C" {
+
+TagLib_IOStream *taglib_shim_iostream_new(const TagLib_Shim_Stream_Callbacks *callbacks)
+{
+  return reinterpret_cast<TagLib_IOStream *>(
+    static_cast<IOStream *>(new CallbackStream(*callbacks)));
+}
+
+TagLib_File *taglib_shim_file_new_iostream_type(TagLib_IOStream *stream,
+                                                TagLib_File_Type type)
+{
+  File *file = newFile(type, reinterpret_cast<IOStream *>(stream), true,
+                       AudioProperties::Average);
+  return file ? reinterpret_cast<TagLib_File *>(new FileRef(file)) : nullptr;
+}
+
+BOOL taglib_shim_file_has_file(TagLib_File *file)
+{
+  return reinterpret_cast<FileRef *>(file)->file() != nullptr;
+}
+
+BOOL taglib_shim_file_read_only(TagLib_File *file)
+{
+  const File *f = reinterpret_cast<FileRef *>(file)->file();
+  return f && f->readOnly();
+}
+
+BOOL taglib_shim_file_save(TagLib_File *file, int *error)
+{
+  // TagLib ignores most failed writes to files opened by name, but the C
+  // library still sets errno for them
+  errno = 0;
+  bool saved = reinterpret_cast<FileRef *>(file)->save();
+  *error = errno;
+  return saved;
+}
+
+void taglib_shim_add_file_type_resolver(TagLib_Shim_Resolve_Callback resolve)
+{
+  // TagLib keeps the resolver until the program exits
+  FileRef::addFileTypeResolver(new CallbackResolver(resolve));
+}
+
+int taglib_shim_audioproperties_length_ms(const TagLib_AudioProperties *audioProperties)
+{
+  return reinterpret_cast<const AudioProperties *>(audioProperties)->lengthInMilliseconds();
+}
+
+BOOL taglib_shim_file_type(const TagLib_File *file, TagLib_File_Type *type)
+{
+  const File *f = reinterpret_cast<const FileRef *>(file)->file();
+  if(isFile<MPEG::File>(f))
+    *type = TagLib_File_MPEG;
+  else if(isFile<Ogg::Vorbis::File>(f))
+    *type = TagLib_File_OggVorbis;
+  else if(isFile<FLAC::File>(f))
+    *type = TagLib_File_FLAC;
+  else if(isFile<MPC::File>(f))
+    *type = TagLib_File_MPC;
+  else if(isFile<Ogg::FLAC::File>(f))
+    *type = TagLib_File_OggFlac;
+  else if(isFile<WavPack::File>(f))
+    *type = TagLib_File_WavPack;
+  else if(isFile<Ogg::Speex::File>(f))
+    *type = TagLib_File_Speex;
+  else if(isFile<TrueAudio::File>(f))
+    *type = TagLib_File_TrueAudio;
+  else if(isFile<MP4::File>(f))
+    *type = TagLib_File_MP4;
+  else if(isFile<ASF::File>(f))
+    *type = TagLib_File_ASF;
+  else if(isFile<RIFF::AIFF::File>(f))
+    *type = TagLib_File_AIFF;
+  else if(isFile<RIFF::WAV::File>(f))
+    *type = TagLib_File_WAV;
+  else if(isFile<APE::File>(f))
+    *type = TagLib_File_APE;
+  else if(isFile<IT::File>(f))
+    *type = TagLib_File_IT;
+  else if(isFile<Mod::File>(f))
+    *type = TagLib_File_Mod;
+  else if(isFile<S3M::File>(f))
+    *type = TagLib_File_S3M;
+  else if(isFile<XM::File>(f))
+    *type = TagLib_File_XM;
+  else if(isFile<Ogg::Opus::File>(f))
+    *type = TagLib_File_Opus;
+  else if(isFile<DSF::File>(f))
+    *type = TagLib_File_DSF;
+  else if(isFile<DSDIFF::File>(f))
+    *type = TagLib_File_DSDIFF;
+  else
+    return false;
+  return true;
+}
+
+const char *taglib_shim_memory_iostream_data(TagLib_IOStream *stream,
+                                             unsigned int *size)
+{
+  auto *s = dynamic_cast<ByteVectorStream *>(reinterpret_cast<IOStream *>(stream));
+  if(!s) {
+    *size = 0;
+    return nullptr;
+  }
+  ByteVector *data = s->data();
+  *size = data->size();
+  return data->isEmpty() ? "" : data->data();
+}
+
+char ***taglib_shim_property_map(const TagLib_File *file, BOOL unicode)
+{
+  return copyPropertyMap(reinterpret_cast<const FileRef *>(file)->properties(), unicode);
+}
+
+char ***taglib_shim_set_property_map(TagLib_File *file, const char *const *const *props,
+                                     BOOL unicode)
+{
+  PropertyMap map;
+  for(; *props; ++props) {
+    const char *const *prop = *props;
+    StringList values;
+    for(const char *const *v = prop + 1; *v; ++v)
+      values.append(toString(*v, unicode));
+    map.insert(toString(prop[0], unicode), values);
+  }
+  return copyPropertyMap(reinterpret_cast<FileRef *>(file)->setProperties(map), unicode);
+}
+
+char **taglib_shim_unsupported_properties(const TagLib_File *file, BOOL unicode)
+{
+  const PropertyMap map = reinterpret_cast<const FileRef *>(file)->properties();
+  return copyStringList(map.unsupportedData(), unicode);
+}
+
+void taglib_shim_remove_unsupported_properties(TagLib_File *file, const char *const *props,
+                                               BOOL unicode)
+{
+  File *f = reinterpret_cast<FileRef *>(file)->file();
+  if(!f)
+    return;
+
+  StringList list;
+  for(; *props; ++props)
+    list.append(toString(*props, unicode));
+  f->removeUnsupportedProperties(list);
+}
+
+BOOL taglib_shim_complex_property_set_list(
+  TagLib_File *file, const char *key,
+  const TagLib_Complex_Property_Attribute *const *const *values, BOOL unicode)
+{
+  List<VariantMap> list;
+  for(; *values; ++values) {
+    VariantMap map;
+    for(const TagLib_Complex_Property_Attribute *const *attr = *values; *attr; ++attr)
+      map.insert(toString((*attr)->key, unicode), toVariant((*attr)->value, unicode));
+    list.append(map);
+  }
+  return reinterpret_cast<FileRef *>(file)->setComplexProperties(toString(key, unicode), list);
+}
+
+void taglib_shim_string_list_free(char **list)
+{
+  for(char **p = list; *p; ++p)
+    delete[] *p;
+  delete[] list;
+}
+
+void taglib_shim_property_map_free(char ***props)
+{
+  for(char ***p = props; *p; ++p) {
+    for(char **v = *p; *v; ++v)
+      delete[] *v;
+    delete[] *p;
+  }
+  delete[] props;
+}
+
+}
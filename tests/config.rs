@@ -0,0 +1,61 @@
+// Copyright 2015  Emmanuele Bassi. All rights reserved.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! The settings in `taglib::config` only apply until the first file is
+//! opened, so they are tested in a process of their own, in one test that
+//! changes them all before opening any file.
+
+use std::fs;
+
+use taglib::config::{self, Id3v2Encoding};
+use taglib::{File, FileError, SettingsLockedError};
+
+const TEST_MP3: &str = "fixtures/test.mp3";
+
+#[test]
+fn test_settings() {
+    let data = fs::read(TEST_MP3).unwrap();
+
+    config::set_id3v2_default_text_encoding(Id3v2Encoding::Utf16).unwrap();
+    config::set_strings_unicode(false).unwrap();
+    assert_eq!(config::id3v2_default_text_encoding(), Some(Id3v2Encoding::Utf16));
+    assert!(!config::strings_unicode());
+
+    let mut file = File::from_bytes(&data, None).unwrap();
+
+    // the settings are locked once a file was opened
+    assert_eq!(config::set_id3v2_default_text_encoding(Id3v2Encoding::Utf8),
+               Err(SettingsLockedError));
+    assert_eq!(config::set_strings_unicode(true), Err(SettingsLockedError));
+    assert_eq!(config::id3v2_default_text_encoding(), Some(Id3v2Encoding::Utf16));
+    assert!(!config::strings_unicode());
+
+    // new ID3v2 frames use the default encoding
+    file.tag_mut().unwrap().set_title("Title").unwrap();
+    file.save().unwrap();
+    let utf16: Vec<u8> = "Title".encode_utf16().flat_map(u16::to_le_bytes).collect();
+    let bytes = file.bytes().unwrap();
+    assert!(bytes.windows(utf16.len()).any(|w| w == utf16.as_slice()));
+
+    // strings are exchanged as Latin-1
+    file.tag_mut().unwrap().set_album("Crème").unwrap();
+    assert_eq!(file.tag().unwrap().album().unwrap(), "Crème");
+    assert!(matches!(file.tag_mut().unwrap().set_album("€"), Err(FileError::InvalidValue(_))));
+}